@@ -0,0 +1,213 @@
+use std::collections::VecDeque;
+use eframe::egui;
+use encoding_rs::Encoding;
+use crate::app::TextEditorApp;
+
+// Something to do once the UI is up. The command line queues these at
+// startup, and anything else that wants the editor to open a file or run a
+// command from outside (e.g. another instance) can queue them too.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    OpenFile(String),
+    SwitchToFile(String), // Brings the tab with this file to the front, without loading it again
+    ReopenWithEncoding(&'static Encoding),
+    GotoLine { line: usize, column: Option<usize> }, // Both 1-based
+    ScrollTo { x: u32, y: u32 }, // Scroll offset of the editor in points
+    RunCommand(Command),
+    RunCommandInTab { tab: u64, command: Command }, // Brings the tab with this id to the front first; nothing happens if it was closed
+    CloseOtherTabs(u64), // Closes every tab but the one with this id
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    New,
+    Open,
+    Save,
+    SaveAs,
+    Undo,
+    Redo,
+    CloseTab,
+    CloseOtherTabs,
+    NextTab,
+    PreviousTab,
+    ToggleReadOnly,
+    UndoReplaceInFiles,
+    Quit,
+}
+
+impl Command {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "new" => Some(Command::New),
+            "open" => Some(Command::Open),
+            "save" => Some(Command::Save),
+            "save-as" => Some(Command::SaveAs),
+            "undo" => Some(Command::Undo),
+            "redo" => Some(Command::Redo),
+            "close-tab" => Some(Command::CloseTab),
+            "close-other-tabs" => Some(Command::CloseOtherTabs),
+            "next-tab" => Some(Command::NextTab),
+            "previous-tab" => Some(Command::PreviousTab),
+            "toggle-read-only" => Some(Command::ToggleReadOnly),
+            "undo-replace-in-files" => Some(Command::UndoReplaceInFiles),
+            "quit" => Some(Command::Quit),
+            _ => None,
+        }
+    }
+}
+
+// Turns command line arguments (without the program name) into actions:
+//   amend FILE                 open FILE
+//   amend FILE:LINE[:COL]      open FILE at a line
+//   amend --line LINE[:COL]    go to a line in the file opened before it
+//   amend --run COMMAND        run a command (new, open, save, save-as, undo, redo,
+//                              close-tab, close-other-tabs, next-tab, previous-tab,
+//                              toggle-read-only, undo-replace-in-files, quit)
+pub fn actions_from_args<I: IntoIterator<Item = String>>(args: I) -> VecDeque<Action> {
+    let mut actions = VecDeque::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--line" => {
+                if let Some(action) = args.next().and_then(|position| parse_position(&position)) {
+                    actions.push_back(action);
+                }
+            }
+            "--run" => {
+                if let Some(command) = args.next().and_then(|name| Command::from_name(&name)) {
+                    actions.push_back(Action::RunCommand(command));
+                }
+            }
+            _ => {
+                // A file that exists wins over a FILE:LINE reading of the name
+                match split_position(&arg) {
+                    Some((path, position)) if !std::path::Path::new(&arg).exists() => {
+                        actions.push_back(Action::OpenFile(path.to_string()));
+                        actions.push_back(position);
+                    }
+                    _ => actions.push_back(Action::OpenFile(arg)),
+                }
+            }
+        }
+    }
+    actions
+}
+
+// Splits "FILE:LINE" or "FILE:LINE:COL" from the end, so "C:\file.txt:3" works
+fn split_position(arg: &str) -> Option<(&str, Action)> {
+    let (rest, last) = arg.rsplit_once(':')?;
+    let last = last.parse().ok()?;
+    if let Some((path, line)) = rest.rsplit_once(':') {
+        if let Ok(line) = line.parse() {
+            return Some((path, Action::GotoLine { line, column: Some(last) }));
+        }
+    }
+    Some((rest, Action::GotoLine { line: last, column: None }))
+}
+
+// "LINE" or "LINE:COL"
+fn parse_position(text: &str) -> Option<Action> {
+    let (line, column) = match text.split_once(':') {
+        Some((line, column)) => (line, Some(column.parse().ok()?)),
+        None => (text, None),
+    };
+    Some(Action::GotoLine {
+        line: line.parse().ok()?,
+        column,
+    })
+}
+
+impl TextEditorApp {
+    pub fn queue_action(&mut self, action: Action) {
+        self.pending_actions.push_back(action);
+    }
+
+    // Runs queued actions in order. An action that comes after opening a file
+    // waits until the file has finished loading, so it applies to that file,
+    // and everything waits while the user is asked about unsaved changes.
+    pub fn run_pending_actions(&mut self, ctx: &egui::Context) {
+        while !self.is_loading() && !self.is_modal_open() {
+            let Some(action) = self.pending_actions.pop_front() else {
+                return;
+            };
+            match action {
+                Action::OpenFile(path) => self.open_in_tab(path),
+                Action::SwitchToFile(path) => self.switch_to_file(&path),
+                Action::ReopenWithEncoding(encoding) => self.reopen_with_encoding(encoding),
+                Action::GotoLine { line, column } => self.goto_line(ctx, line, column),
+                Action::ScrollTo { x, y } => {
+                    // Takes over from the scrolling a GotoLine before it asked for
+                    self.view.select = None;
+                    self.view.scroll_to = Some(egui::vec2(x as f32, y as f32));
+                }
+                Action::RunCommand(command) => self.run_command(ctx, command),
+                Action::RunCommandInTab { tab, command } => {
+                    if self.tabs.iter().any(|t| t.id == tab) {
+                        self.switch_to_tab_id(tab);
+                        self.run_command(ctx, command);
+                    }
+                }
+                Action::CloseOtherTabs(keep) => self.close_other_tabs(keep),
+            }
+        }
+    }
+
+    // The command for the tab in front now, e.g. for a notification's
+    // button, which may be clicked after another tab was brought up
+    pub fn in_this_tab(&self, command: Command) -> Action {
+        Action::RunCommandInTab { tab: self.tabs[self.active_tab].id, command }
+    }
+
+    pub fn run_command(&mut self, ctx: &egui::Context, command: Command) {
+        match command {
+            Command::New => self.new_tab(),
+            Command::Open => self.open_file(),
+            Command::Save => self.save_file(),
+            Command::SaveAs => self.save_file_as(),
+            Command::Undo if !self.read_only => self.undo(ctx),
+            Command::Redo if !self.read_only => self.redo(ctx),
+            Command::Undo | Command::Redo => {}
+            Command::CloseTab => self.close_tab(),
+            Command::CloseOtherTabs => self.close_other_tabs(self.tabs[self.active_tab].id),
+            Command::NextTab => self.cycle_tabs(true),
+            Command::PreviousTab => self.cycle_tabs(false),
+            Command::ToggleReadOnly => self.toggle_read_only(),
+            Command::UndoReplaceInFiles => self.undo_replace_in_files(ctx),
+            Command::Quit => {
+                // Remember the tabs before any of them are closed below
+                if self.quit_session.is_none() {
+                    self.quit_session = Some(self.session_files(ctx));
+                }
+                if self.confirm_close_all() {
+                    if let Some(files) = self.quit_session.take() {
+                        self.save_open_files(files);
+                    }
+                    self.closing = true;
+                    self.discard_all_autosaves();
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                }
+            }
+        }
+    }
+
+    // Line and column are 1-based and clamped to the document
+    pub fn goto_line(&mut self, ctx: &egui::Context, line: usize, column: Option<usize>) {
+        let line = line.max(1) - 1;
+        if let Some(large_file) = &mut self.large_file {
+            large_file.goto_line(line);
+            return;
+        }
+        let rope = self.document.rope();
+        let line = line.min(rope.len_lines() - 1);
+        let line_start = rope.line_to_char(line);
+        let text = rope.line(line);
+        let mut line_len = text.len_chars();
+        while line_len > 0 && matches!(text.char(line_len - 1), '\n' | '\r') {
+            line_len -= 1;
+        }
+        let char_index = line_start + column.map_or(0, |c| c.max(1) - 1).min(line_len);
+        // Places the cursor and scrolls it into view once the editor is shown
+        self.view.select = Some((char_index, char_index));
+        ctx.memory_mut(|m| m.request_focus(self.editor_id()));
+    }
+}
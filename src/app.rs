@@ -1,60 +1,130 @@
-use std::sync::{Arc, Mutex};
+use std::collections::{HashSet, VecDeque};
+use ropey::Rope;
+use crate::actions::Action;
+use crate::dialogs::{ReopenDialog, SaveAsDialog, UnsavedChangesDialog};
+use crate::document::Document;
+use crate::external_change::{ExternalChangeDialog, FileStamp};
+use crate::find::FindBar;
+use crate::find_in_files::FilesPanel;
+use crate::goto::GotoDialog;
+use crate::replace_in_files::Replaced;
+use crate::hex_editor::HexEditor;
+use crate::history::{HistoryPanel, Retention};
+use crate::large_file::LargeFile;
+use crate::load_job::LoadJob;
+use crate::notifications::Notifications;
+use crate::recovery::{Autosave, RecoveryDialog};
+use crate::session::{Session, SessionFile};
+use crate::tabs::{EditorView, Tab};
+use crate::undo::DEFAULT_UNDO_DEPTH;
 
 pub struct TextEditorApp {
-    pub text: String,
+    pub document: Document,
     pub filename: Option<String>,
-    pub is_modified: bool,
-    pub is_loading: bool,
-    pub loading_filename: Option<String>,
-    pub pending_file_content: Arc<Mutex<Option<(String, String)>>>, // (filename, content)
-    pub pending_file_to_load: Option<String>, // File to load after UI is ready
-    pub loading_progress: f32, // Progress indicator (0.0 to 1.0)
-    pub partial_content: Arc<Mutex<String>>, // Content being loaded progressively
-    pub bytes_loaded: Arc<Mutex<usize>>, // Number of bytes loaded so far
-    pub total_bytes: Arc<Mutex<usize>>, // Total file size
+    pub large_file: Option<LargeFile>, // Set while a file too big to edit is open in the viewer
+    pub hex_editor: Option<HexEditor>, // Set while a binary file is open in the hex editor
+    pub disk_stamp: Option<FileStamp>, // The file on disk as of the last load or save
+    pub window_focused: bool,
+    pub read_only: bool, // Edits are blocked, because the file can't be written or the user asked for it
+    pub write_error: Option<String>, // Why the open file can't be saved where it is
+    pub invalid_bytes: usize, // Invalid UTF-8 bytes replaced while loading the current file
+    pub notifications: Notifications,
+    pub autosave: Autosave,
+    pub recovery_dialog: Option<RecoveryDialog>,
+    pub save_as_dialog: Option<SaveAsDialog>,
+    pub reopen_dialog: Option<ReopenDialog>,
+    pub goto_dialog: Option<GotoDialog>,
+    pub unsaved_changes_dialog: Option<UnsavedChangesDialog>,
+    pub external_change_dialog: Option<ExternalChangeDialog>,
+    pub discard_confirmed: bool, // The user chose Don't Save; lets the next confirm_discard through
+    pub after_save: Option<Action>, // Runs once the document has been saved from the unsaved changes prompt
+    pub closing: bool, // The window is allowed to close
+    pub undo_depth: usize, // Maximum number of undo steps kept per document
+    pub history_retention: Retention, // How many versions of each file the local history keeps, and for how long
+    pub too_big_for_history: HashSet<String>, // Files the user was told aren't kept in the history, so it's said once
+    pub history_panel: Option<HistoryPanel>,
+    pub find_bar: Option<FindBar>,
+    pub files_panel: Option<FilesPanel>,
+    pub replaced_in_files: Vec<Replaced>, // What the last replace in files changed, for undoing it
+    pub save_in_place: bool, // Overwrite files directly instead of replacing them, so links stay intact
+    pub load_job: Option<LoadJob>, // The file being loaded, or the error from the last load that failed
+    pub pending_actions: VecDeque<Action>, // Run in order once the UI is ready
+    pub tabs: Vec<Tab>, // All open documents in tab order; the active one's state is in the fields above
+    pub active_tab: usize,
+    pub next_tab_id: u64,
+    pub view: EditorView,
+    pub session: Session, // Recent files and what was open at the last quit
+    pub quit_session: Option<(Vec<SessionFile>, usize)>, // The open files as of when quitting started
+    pub window_title: String, // Last title sent to the window, so it's only sent when it changes
 }
 
 impl Default for TextEditorApp {
     fn default() -> Self {
         Self {
-            text: String::new(),
+            document: Document::new(),
             filename: None,
-            is_modified: false,
-            is_loading: false,
-            loading_filename: None,
-            pending_file_content: Arc::new(Mutex::new(None)),
-            pending_file_to_load: None,
-            loading_progress: 0.0,
-            partial_content: Arc::new(Mutex::new(String::new())),
-            bytes_loaded: Arc::new(Mutex::new(0)),
-            total_bytes: Arc::new(Mutex::new(0)),
+            large_file: None,
+            hex_editor: None,
+            disk_stamp: None,
+            window_focused: false,
+            read_only: false,
+            write_error: None,
+            invalid_bytes: 0,
+            notifications: Notifications::default(),
+            autosave: Autosave::default(),
+            recovery_dialog: None,
+            save_as_dialog: None,
+            reopen_dialog: None,
+            goto_dialog: None,
+            unsaved_changes_dialog: None,
+            external_change_dialog: None,
+            discard_confirmed: false,
+            after_save: None,
+            closing: false,
+            undo_depth: DEFAULT_UNDO_DEPTH,
+            history_retention: Retention::default(),
+            too_big_for_history: HashSet::new(),
+            history_panel: None,
+            find_bar: None,
+            files_panel: None,
+            replaced_in_files: Vec::new(),
+            save_in_place: false,
+            load_job: None,
+            pending_actions: VecDeque::new(),
+            tabs: vec![Tab::default()],
+            active_tab: 0,
+            next_tab_id: 1,
+            view: EditorView::default(),
+            session: Session::default(),
+            quit_session: None,
+            window_title: String::new(),
         }
     }
 }
 
 impl TextEditorApp {
     pub fn new_file(&mut self) {
-        if self.is_modified {
-            // In a real app, you'd ask the user if they want to save
-            // For now, we'll just clear without asking
-        }
-        self.text.clear();
+        self.document = self.new_document(Rope::new());
         self.filename = None;
-        self.is_modified = false;
-        self.is_loading = false;
-        self.loading_filename = None;
-        self.pending_file_to_load = None;
-        self.loading_progress = 0.0;
-        
-        // Clear partial content and counters
-        if let Ok(mut partial) = self.partial_content.lock() {
-            *partial = String::new();
-        }
-        if let Ok(mut bytes_loaded) = self.bytes_loaded.lock() {
-            *bytes_loaded = 0;
-        }
-        if let Ok(mut total_bytes) = self.total_bytes.lock() {
-            *total_bytes = 0;
-        }
+        self.large_file = None;
+        self.hex_editor = None;
+        self.invalid_bytes = 0;
+        self.read_only = false;
+        self.write_error = None;
+        self.disk_stamp = None;
+        self.external_change_dialog = None;
+        self.cancel_loading();
+        self.load_job = None;
+        self.view = EditorView::default();
+    }
+    
+    pub fn has_unsaved_changes(&self) -> bool {
+        self.document.is_modified() || self.hex_editor.as_ref().is_some_and(|hex| hex.modified)
+    }
+    
+    pub fn new_document(&self, rope: Rope) -> Document {
+        let mut document = Document::from_rope(rope);
+        document.set_undo_depth(self.undo_depth);
+        document
     }
 }
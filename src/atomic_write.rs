@@ -0,0 +1,187 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// Saves the bytes to the file. Normally the new contents go to a temporary
+// file that then replaces the original, so a crash or a full disk can't
+// leave a truncated file behind. Writing in place instead keeps symlinks and
+// hard links pointing at the same file; it's used when asked for, when the
+// file has other hard links, and when no temporary file can be created.
+pub fn write_file(path: &Path, bytes: &[u8], in_place: bool) -> io::Result<()> {
+    if in_place || has_other_links(path) {
+        return write_in_place(path, bytes);
+    }
+    match write_atomic(path, |out| out.write_all(bytes)) {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied && path.exists() => write_in_place(path, bytes),
+        result => result,
+    }
+}
+
+// Writes to a temporary file next to the target, syncs it and renames it over
+// the target. The replacement gets the original's permissions and owner.
+pub fn write_atomic(path: &Path, write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>) -> io::Result<()> {
+    // Replace the file a symlink points to rather than the link itself
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let original = fs::metadata(&path).ok();
+    let temp_path = temp_path_for(&path);
+    let _ = fs::remove_file(&temp_path); // Left over from a crash
+
+    let result = (|| {
+        let file = OpenOptions::new().write(true).create_new(true).open(&temp_path)?;
+        let mut out = BufWriter::new(file);
+        write(&mut out)?;
+        let file = out.into_inner().map_err(|e| e.into_error())?;
+        if let Some(original) = &original {
+            copy_metadata(&file, original)?;
+        }
+        file.sync_all()?;
+        fs::rename(&temp_path, &path)?;
+        sync_parent(&path);
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+pub fn write_in_place(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+fn temp_path_for(path: &Path) -> PathBuf {
+    let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.amend-tmp", file_name, std::process::id()))
+}
+
+fn copy_metadata(file: &File, original: &fs::Metadata) -> io::Result<()> {
+    file.set_permissions(original.permissions())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // Fails with PermissionDenied for someone else's file, which makes
+        // write_file fall back to writing in place rather than take it over
+        std::os::unix::fs::fchown(file, Some(original.uid()), Some(original.gid()))?;
+    }
+    Ok(())
+}
+
+fn has_other_links(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        fs::metadata(path).map(|m| m.nlink() > 1).unwrap_or(false)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        false
+    }
+}
+
+// Makes the rename itself durable
+fn sync_parent(path: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("amend-atomic-write-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[cfg(unix)]
+    fn mode_and_inode(path: &Path) -> (u32, u64) {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = fs::metadata(path).unwrap();
+        (metadata.mode() & 0o7777, metadata.ino())
+    }
+
+    #[test]
+    fn replaces_the_file_through_a_temporary_one() {
+        let dir = temp_dir("rename");
+        let path = dir.join("file.txt");
+        write_file(&path, b"new file", false).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new file");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        }
+        #[cfg(unix)]
+        let (_, inode) = mode_and_inode(&path);
+        write_file(&path, b"replaced", false).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"replaced");
+        #[cfg(unix)]
+        {
+            let (mode, new_inode) = mode_and_inode(&path);
+            assert_eq!(mode, 0o640);
+            assert_ne!(new_inode, inode, "a new file was renamed over the old one");
+        }
+        // Nothing but the file is left behind
+        let entries: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(entries, ["file.txt"]);
+    }
+
+    #[test]
+    fn writes_in_place_when_asked() {
+        let dir = temp_dir("in-place");
+        let path = dir.join("file.txt");
+        fs::write(&path, b"a longer original").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        }
+        #[cfg(unix)]
+        let before = mode_and_inode(&path);
+        write_file(&path, b"short", true).unwrap();
+        let contents = fs::read(&path).unwrap();
+        #[cfg(unix)]
+        let after = mode_and_inode(&path);
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(contents, b"short");
+        #[cfg(unix)]
+        assert_eq!(after, before, "same file, same permissions");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_keep_pointing_at_the_file() {
+        let dir = temp_dir("links");
+        let path = dir.join("file.txt");
+        let hard_link = dir.join("hard.txt");
+        let symlink = dir.join("symlink.txt");
+        fs::write(&path, b"original").unwrap();
+        fs::hard_link(&path, &hard_link).unwrap();
+        std::os::unix::fs::symlink(&path, &symlink).unwrap();
+
+        // Another hard link makes it write in place, so both names see the change
+        write_file(&hard_link, b"through the hard link", false).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"through the hard link");
+
+        fs::remove_file(&hard_link).unwrap();
+        write_file(&symlink, b"through the symlink", false).unwrap();
+        let is_symlink = fs::symlink_metadata(&symlink).unwrap().file_type().is_symlink();
+        let contents = fs::read(&path).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        assert!(is_symlink);
+        assert_eq!(contents, b"through the symlink");
+    }
+}
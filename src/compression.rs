@@ -0,0 +1,121 @@
+use std::borrow::Cow;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+// Longest magic number below
+pub const MAGIC_LEN: usize = 6;
+
+// Compressed formats opened and saved transparently
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl Codec {
+    // Recognises a compressed stream by its first bytes
+    pub fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Some(Codec::Gzip)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Codec::Zstd)
+        } else if header.starts_with(b"BZh") && header.get(3).is_some_and(|level| (b'1'..=b'9').contains(level)) {
+            Some(Codec::Bzip2)
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Codec::Xz)
+        } else {
+            None
+        }
+    }
+
+    // For a new file, going by its name, e.g. when saving as "out.json.zst"
+    pub fn from_extension(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "gz" | "gzip" => Some(Codec::Gzip),
+            "zst" | "zstd" => Some(Codec::Zstd),
+            "bz2" => Some(Codec::Bzip2),
+            "xz" => Some(Codec::Xz),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Gzip => "gzip",
+            Codec::Zstd => "zstd",
+            Codec::Bzip2 => "bzip2",
+            Codec::Xz => "xz",
+        }
+    }
+
+    // Decompresses everything the reader yields. Files made of several
+    // concatenated streams (e.g. from appending to a .gz log) read as one.
+    pub fn decoder<'a>(&self, reader: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Codec::Gzip => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+            Codec::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+            Codec::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+            Codec::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(reader)),
+        })
+    }
+
+    // Compresses the bytes at each format's usual default level
+    pub fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(bytes.len() / 4);
+        match self {
+            Codec::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(&mut out, flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()?;
+            }
+            Codec::Zstd => {
+                let mut encoder = zstd::stream::write::Encoder::new(&mut out, 0)?;
+                encoder.write_all(bytes)?;
+                encoder.finish()?;
+            }
+            Codec::Bzip2 => {
+                let mut encoder = bzip2::write::BzEncoder::new(&mut out, bzip2::Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()?;
+            }
+            Codec::Xz => {
+                let mut encoder = xz2::write::XzEncoder::new(&mut out, 6);
+                encoder.write_all(bytes)?;
+                encoder.finish()?;
+            }
+        }
+        Ok(out)
+    }
+}
+
+// The bytes to write for a file that may be compressed
+pub fn compress_with(codec: Option<Codec>, bytes: &[u8]) -> io::Result<Cow<'_, [u8]>> {
+    match codec {
+        Some(codec) => Ok(Cow::Owned(codec.compress(bytes)?)),
+        None => Ok(Cow::Borrowed(bytes)),
+    }
+}
+
+// The bytes as they are once decompressed, for comparing a file on disk
+// with the document. Bytes that aren't compressed, or don't decompress,
+// come back unchanged.
+pub fn decompress_if_compressed(bytes: Vec<u8>) -> Vec<u8> {
+    let Some(codec) = Codec::detect(&bytes) else {
+        return bytes;
+    };
+    let mut decompressed = Vec::new();
+    match codec.decoder(bytes.as_slice()).and_then(|mut decoder| decoder.read_to_end(&mut decompressed)) {
+        Ok(_) => decompressed,
+        Err(_) => bytes,
+    }
+}
+
+// Whether the file starts like a compressed stream
+pub fn detect_file(path: impl AsRef<Path>) -> Option<Codec> {
+    let mut header = Vec::with_capacity(MAGIC_LEN);
+    std::fs::File::open(path).ok()?.take(MAGIC_LEN as u64).read_to_end(&mut header).ok()?;
+    Codec::detect(&header)
+}
@@ -0,0 +1,118 @@
+use encoding_rs::{DecoderResult, Encoding};
+
+// Turns a file read in arbitrary chunks into text. A multi-byte character
+// split across two chunks is carried over to the next call instead of being
+// lost, and every invalid byte is replaced with U+FFFD and counted so the UI
+// can tell the user the text is not an exact copy of the file.
+pub struct StreamDecoder {
+    decoder: encoding_rs::Decoder,
+    invalid_bytes: usize,
+}
+
+impl StreamDecoder {
+    // The BOM, if any, must already have been skipped
+    pub fn new(encoding: &'static Encoding) -> Self {
+        Self {
+            decoder: encoding.new_decoder_without_bom_handling(),
+            invalid_bytes: 0,
+        }
+    }
+
+    pub fn invalid_bytes(&self) -> usize {
+        self.invalid_bytes
+    }
+
+    pub fn decode(&mut self, input: &[u8], out: &mut String) {
+        self.run(input, out, false);
+    }
+
+    // Call once the whole input has been seen; a dangling partial character is invalid
+    pub fn finish(&mut self, out: &mut String) {
+        self.run(&[], out, true);
+    }
+
+    fn run(&mut self, mut input: &[u8], out: &mut String, last: bool) {
+        loop {
+            // The decoder only writes into spare capacity
+            let needed = self
+                .decoder
+                .max_utf8_buffer_length_without_replacement(input.len())
+                .unwrap_or(input.len() * 3 + 16);
+            out.reserve(needed);
+
+            let (result, read) = self.decoder.decode_to_string_without_replacement(input, out, last);
+            input = &input[read..];
+            match result {
+                DecoderResult::InputEmpty => return,
+                DecoderResult::OutputFull => {}
+                DecoderResult::Malformed(bad_bytes, _) => {
+                    self.invalid_bytes += bad_bytes as usize;
+                    out.push(char::REPLACEMENT_CHARACTER);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{UTF_16BE, UTF_16LE, UTF_8};
+
+    const TEXT: &str = "aé€😀b\r\nz";
+
+    // Decodes the bytes fed in two chunks, split at `at`
+    fn decode_split(encoding: &'static Encoding, bytes: &[u8], at: usize) -> (String, usize) {
+        let mut decoder = StreamDecoder::new(encoding);
+        let mut out = String::new();
+        decoder.decode(&bytes[..at], &mut out);
+        decoder.decode(&bytes[at..], &mut out);
+        decoder.finish(&mut out);
+        (out, decoder.invalid_bytes())
+    }
+
+    fn utf16(text: &str, big_endian: bool) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|unit| if big_endian { unit.to_be_bytes() } else { unit.to_le_bytes() })
+            .collect()
+    }
+
+    #[test]
+    fn characters_split_across_chunks() {
+        let inputs = [
+            (UTF_8, TEXT.as_bytes().to_vec()),
+            (UTF_16LE, utf16(TEXT, false)),
+            (UTF_16BE, utf16(TEXT, true)),
+        ];
+        for (encoding, bytes) in inputs {
+            for at in 0..=bytes.len() {
+                assert_eq!(decode_split(encoding, &bytes, at), (TEXT.to_string(), 0), "{} split at {}", encoding.name(), at);
+            }
+        }
+    }
+
+    #[test]
+    fn one_byte_at_a_time() {
+        let bytes = utf16(TEXT, false);
+        let mut decoder = StreamDecoder::new(UTF_16LE);
+        let mut out = String::new();
+        for byte in &bytes {
+            decoder.decode(std::slice::from_ref(byte), &mut out);
+        }
+        decoder.finish(&mut out);
+        assert_eq!(out, TEXT);
+    }
+
+    #[test]
+    fn invalid_bytes_are_counted() {
+        let bytes = b"a\xFFb\xE2\x82";
+        for at in 0..=bytes.len() {
+            let (text, invalid) = decode_split(UTF_8, bytes, at);
+            assert_eq!(text, "a\u{FFFD}b\u{FFFD}", "split at {}", at);
+            // The unfinished "€" at the end is found invalid by finish
+            assert_eq!(invalid, 3, "split at {}", at);
+        }
+        // A lone half of a UTF-16 code unit at the end
+        assert_eq!(decode_split(UTF_16LE, b"h\0i", 1), ("h\u{FFFD}".to_string(), 1));
+    }
+}
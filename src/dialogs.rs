@@ -0,0 +1,187 @@
+use eframe::egui;
+use encoding_rs::{Encoding, UTF_8};
+use crate::actions::Action;
+use crate::app::TextEditorApp;
+use crate::encoding::{TextEncoding, SUPPORTED_ENCODINGS};
+
+// The native save dialog has no room for extra options, so Save As asks
+// for the encoding first and opens the file picker from here
+pub struct SaveAsDialog {
+    pub encoding: &'static Encoding,
+    pub bom: bool, // Only offered for UTF-8; UTF-16 always gets one
+}
+
+pub struct ReopenDialog {
+    pub encoding: &'static Encoding,
+}
+
+// Asked before anything that would throw away unsaved changes
+pub struct UnsavedChangesDialog {
+    pub then: Action, // What the user was doing, carried on with unless they cancel
+}
+
+impl TextEditorApp {
+    pub fn show_dialogs(&mut self, ctx: &egui::Context) {
+        self.show_save_as_dialog(ctx);
+        self.show_reopen_dialog(ctx);
+        self.show_goto_dialog(ctx);
+        self.show_unsaved_changes_dialog(ctx);
+        self.show_recovery_dialog(ctx);
+        self.show_external_change_dialog(ctx);
+        self.resume_after_save();
+    }
+    
+    // A question is waiting for an answer; queued actions hold off until then
+    pub fn is_modal_open(&self) -> bool {
+        self.unsaved_changes_dialog.is_some() || self.recovery_dialog.is_some() || self.external_change_dialog.is_some()
+    }
+    
+    // True if nothing would be lost by replacing the document. Otherwise asks
+    // the user, and `then` is queued again once they have saved or said not to.
+    pub fn confirm_discard(&mut self, then: Action) -> bool {
+        if !self.has_unsaved_changes() || std::mem::take(&mut self.discard_confirmed) {
+            return true;
+        }
+        self.unsaved_changes_dialog = Some(UnsavedChangesDialog { then });
+        false
+    }
+    
+    fn show_unsaved_changes_dialog(&mut self, ctx: &egui::Context) {
+        let Some(dialog) = &self.unsaved_changes_dialog else {
+            return;
+        };
+        let name = self.filename.as_deref().unwrap_or("Untitled");
+        let mut save = false;
+        let mut discard = false;
+        let mut cancel = false;
+
+        let modal = egui::Modal::new(egui::Id::new("unsaved_changes")).show(ctx, |ui| {
+            ui.heading("Unsaved Changes");
+            ui.label(format!("Do you want to save the changes to {}?", name));
+            ui.add_space(8.0);
+            ui.horizontal(|ui| {
+                save = ui.button("Save").clicked();
+                discard = ui.button("Don't Save").clicked();
+                cancel = ui.button("Cancel").clicked();
+            });
+        });
+        if modal.should_close() {
+            cancel = true;
+        }
+
+        let then = dialog.then.clone();
+        if save {
+            self.unsaved_changes_dialog = None;
+            // Saving an untitled file goes through the Save As dialog first
+            self.after_save = Some(then);
+            self.save_file();
+        } else if discard {
+            self.unsaved_changes_dialog = None;
+            self.discard_confirmed = true;
+            self.pending_actions.push_front(then);
+        } else if cancel {
+            self.unsaved_changes_dialog = None;
+            // Quitting was called off, so the next quit takes a fresh look at the tabs
+            self.quit_session = None;
+        }
+    }
+    
+    // Carries on with what the unsaved changes prompt interrupted once the
+    // save went through, or forgets it if the save failed or was cancelled
+    fn resume_after_save(&mut self) {
+        if self.after_save.is_none() || self.save_as_dialog.is_some() {
+            return;
+        }
+        if let Some(then) = self.after_save.take() {
+            if !self.has_unsaved_changes() {
+                self.pending_actions.push_front(then);
+            }
+        }
+    }
+
+    fn show_save_as_dialog(&mut self, ctx: &egui::Context) {
+        let Some(dialog) = &mut self.save_as_dialog else {
+            return;
+        };
+        let mut choose_file = false;
+        let mut cancel = false;
+
+        egui::Window::new("Save As")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Encoding:");
+                    encoding_combo(ui, "save_as_encoding", &mut dialog.encoding);
+                });
+                if dialog.encoding == UTF_8 {
+                    ui.checkbox(&mut dialog.bom, "Write byte order mark");
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Choose File…").clicked() {
+                        choose_file = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        cancel = true;
+                    }
+                });
+            });
+
+        if choose_file {
+            let mut encoding = TextEncoding::new(dialog.encoding);
+            if dialog.encoding == UTF_8 {
+                encoding.bom = dialog.bom;
+            }
+            self.save_as_dialog = None;
+            self.save_file_as_with_encoding(encoding);
+        } else if cancel {
+            self.save_as_dialog = None;
+        }
+    }
+
+    fn show_reopen_dialog(&mut self, ctx: &egui::Context) {
+        let Some(dialog) = &mut self.reopen_dialog else {
+            return;
+        };
+        let mut reopen = false;
+        let mut cancel = false;
+
+        egui::Window::new("Reopen with Encoding")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Encoding:");
+                    encoding_combo(ui, "reopen_encoding", &mut dialog.encoding);
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Reopen").clicked() {
+                        reopen = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        cancel = true;
+                    }
+                });
+            });
+
+        if reopen {
+            let encoding = dialog.encoding;
+            self.reopen_dialog = None;
+            self.reopen_with_encoding(encoding);
+        } else if cancel {
+            self.reopen_dialog = None;
+        }
+    }
+}
+
+fn encoding_combo(ui: &mut egui::Ui, id: &str, encoding: &mut &'static Encoding) {
+    egui::ComboBox::from_id_salt(id)
+        .selected_text(encoding.name())
+        .show_ui(ui, |ui| {
+            for candidate in SUPPORTED_ENCODINGS {
+                ui.selectable_value(encoding, *candidate, candidate.name());
+            }
+        });
+}
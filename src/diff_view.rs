@@ -0,0 +1,40 @@
+use eframe::egui;
+use similar::{ChangeTag, TextDiff};
+
+// Changed lines with three lines of context around them
+pub fn diff_lines(old: &str, new: &str) -> Vec<(ChangeTag, String)> {
+    let diff = TextDiff::from_lines(old, new);
+    let mut lines = Vec::new();
+    for group in diff.grouped_ops(3) {
+        if !lines.is_empty() {
+            lines.push((ChangeTag::Equal, "…".to_string()));
+        }
+        for op in group {
+            for change in diff.iter_changes(&op) {
+                let text = change.value().trim_end_matches(['\n', '\r']).to_string();
+                lines.push((change.tag(), text));
+            }
+        }
+    }
+    lines
+}
+
+pub fn show_diff(ui: &mut egui::Ui, lines: &[(ChangeTag, String)]) {
+    egui::ScrollArea::both().max_height(300.0).show(ui, |ui| {
+        if lines.is_empty() {
+            ui.label("No differences.");
+        }
+        for (tag, line) in lines {
+            show_diff_line(ui, *tag, line);
+        }
+    });
+}
+
+pub fn show_diff_line(ui: &mut egui::Ui, tag: ChangeTag, line: &str) {
+    let (prefix, color) = match tag {
+        ChangeTag::Insert => ("+", egui::Color32::from_rgb(0x3f, 0xa0, 0x3f)),
+        ChangeTag::Delete => ("-", ui.visuals().error_fg_color),
+        ChangeTag::Equal => (" ", ui.visuals().weak_text_color()),
+    };
+    ui.label(egui::RichText::new(format!("{}{}", prefix, line)).monospace().color(color));
+}
@@ -0,0 +1,446 @@
+use std::cell::OnceCell;
+use std::fs;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use ropey::Rope;
+use crate::atomic_write::{write_atomic, write_file};
+use crate::compression::Codec;
+use crate::encoding::TextEncoding;
+use crate::line_ending::{LineEnding, LineEndingStats};
+use crate::undo::{Edit, UndoHistory};
+
+// Revisions are unique across documents, so a document that replaced another
+// (e.g. after loading a file) can't be mistaken for it
+fn next_revision() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+// Marks a document that holds only the start of its file
+#[derive(Clone, Copy, Debug)]
+pub struct PartialLoad {
+    pub loaded_bytes: u64, // Length of the file prefix the document was read from
+    pub file_size: u64,
+    pub spliceable: bool, // False if the prefix may end inside a character, so saving is blocked
+}
+
+// The text of the open file. Edits go into a rope, so undo, saving and
+// snapshots don't copy the text: snapshots are cheap clones that share the
+// underlying chunks. The text widget still needs the text in one piece, see
+// rope_insert.
+pub struct Document {
+    rope: Rope,
+    flat: OnceCell<String>, // Contiguous copy for the text widget, made on first use and then edited along with the rope
+    revision: u64, // Changes with every edit, so others can tell when what they worked from is stale
+    history: UndoHistory,
+    encoding: TextEncoding, // Encoding the file was read in, used again when saving
+    line_ending: LineEnding, // Style used for line breaks typed into the document
+    mixed_line_endings: bool,
+    partial: Option<PartialLoad>,
+    compression: Option<Codec>, // Codec the file was compressed with, used again when saving
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Document {
+    pub fn new() -> Self {
+        Self::from_rope(Rope::new())
+    }
+
+    pub fn from_rope(rope: Rope) -> Self {
+        Self {
+            rope,
+            flat: OnceCell::new(),
+            revision: next_revision(),
+            history: UndoHistory::default(),
+            encoding: TextEncoding::default(),
+            line_ending: LineEnding::default(),
+            mixed_line_endings: false,
+            partial: None,
+            compression: None,
+        }
+    }
+
+    // Cheap copy of the current text, safe to hand to another thread
+    pub fn snapshot(&self) -> Rope {
+        self.rope.clone()
+    }
+
+    pub fn rope(&self) -> &Rope {
+        &self.rope
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.rope.len_chars()
+    }
+
+    pub fn len_bytes(&self) -> usize {
+        self.rope.len_bytes()
+    }
+
+    // Edits the rope and the flat copy alike. Patching the copy moves the
+    // bytes after the edit along, which is much cheaper than copying the rope
+    // out again, but still grows with the document: so does the text widget's
+    // layout of the whole text every frame, so typing in a file of tens of
+    // megabytes lags either way. Those open in the large file viewer.
+    fn rope_insert(&mut self, char_index: usize, text: &str) {
+        if let Some(flat) = self.flat.get_mut() {
+            flat.insert_str(self.rope.char_to_byte(char_index), text);
+        }
+        self.rope.insert(char_index, text);
+    }
+
+    fn rope_remove(&mut self, char_range: Range<usize>) {
+        if let Some(flat) = self.flat.get_mut() {
+            flat.replace_range(self.rope.char_to_byte(char_range.start)..self.rope.char_to_byte(char_range.end), "");
+        }
+        self.rope.remove(char_range);
+    }
+
+    pub fn insert(&mut self, char_index: usize, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        self.rope_insert(char_index, text);
+        self.revision = next_revision();
+        self.history.record(Edit::Insert {
+            at: char_index,
+            text: text.to_owned(),
+        });
+    }
+
+    pub fn remove(&mut self, char_range: Range<usize>) {
+        if char_range.start >= char_range.end {
+            return;
+        }
+        let removed = self.rope.slice(char_range.clone()).to_string();
+        self.rope_remove(char_range.clone());
+        self.revision = next_revision();
+        self.history.record(Edit::Delete {
+            at: char_range.start,
+            text: removed,
+        });
+    }
+
+    // Replaces the whole text as one undoable step
+    pub fn replace_all(&mut self, text: &str) {
+        self.begin_undo_group();
+        self.remove(0..self.rope.len_chars());
+        self.insert(0, text);
+        self.end_undo_group();
+    }
+
+    // Replaces each char range with its text as one undoable step, kept apart
+    // from any typing around it. The ranges must be in order and not overlap.
+    pub fn replace_ranges(&mut self, edits: &[(Range<usize>, String)]) {
+        self.break_undo_group();
+        self.begin_undo_group();
+        for (range, text) in edits.iter().rev() {
+            self.remove(range.clone());
+            self.insert(range.start, text);
+        }
+        self.end_undo_group();
+        self.break_undo_group();
+    }
+
+    // Replaces the text and starts a fresh history, e.g. after loading a file
+    pub fn set_rope(&mut self, rope: Rope) {
+        let max_depth = self.history.max_depth();
+        self.rope = rope;
+        self.flat.take();
+        self.revision = next_revision();
+        self.history = UndoHistory::with_depth(max_depth);
+    }
+
+    pub fn encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    pub fn set_encoding(&mut self, encoding: TextEncoding) {
+        self.encoding = encoding;
+    }
+
+    pub fn compression(&self) -> Option<Codec> {
+        self.compression
+    }
+
+    pub fn set_compression(&mut self, compression: Option<Codec>) {
+        self.compression = compression;
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    pub fn has_mixed_line_endings(&self) -> bool {
+        self.mixed_line_endings
+    }
+
+    // Adopts the style found in the text; a text without line breaks keeps the current one
+    pub fn set_line_endings(&mut self, stats: &LineEndingStats) {
+        if let Some(dominant) = stats.dominant() {
+            self.line_ending = dominant;
+        }
+        self.mixed_line_endings = stats.is_mixed();
+    }
+
+    fn refresh_line_endings(&mut self) {
+        let mut stats = LineEndingStats::default();
+        for chunk in self.rope.chunks() {
+            stats.feed(chunk);
+        }
+        stats.finish();
+        self.set_line_endings(&stats);
+    }
+
+    // Rewrites every line break to the given style as a single undo step
+    pub fn convert_line_endings(&mut self, line_ending: LineEnding) {
+        // Ropey never splits a CRLF pair across chunks, so chunks can be converted one by one
+        // CR to LF keeps the length, so compare the text itself
+        let mut converted = String::with_capacity(self.rope.len_bytes());
+        let mut changed = false;
+        for chunk in self.rope.chunks() {
+            let chunk_converted = line_ending.convert(chunk);
+            changed |= chunk_converted != chunk;
+            converted.push_str(&chunk_converted);
+        }
+        if changed {
+            self.replace_all(&converted);
+        }
+        self.line_ending = line_ending;
+        self.mixed_line_endings = false;
+    }
+
+    pub fn partial(&self) -> Option<PartialLoad> {
+        self.partial
+    }
+
+    pub fn set_partial(&mut self, partial: Option<PartialLoad>) {
+        self.partial = partial;
+    }
+
+    pub fn is_modified(&self) -> bool {
+        !self.history.is_at_save_point()
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.history.mark_saved();
+    }
+
+    pub fn set_undo_depth(&mut self, depth: usize) {
+        self.history.set_max_depth(depth);
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
+
+    // Edits made between begin and end are undone as a single step
+    pub fn begin_undo_group(&mut self) {
+        self.history.begin_group();
+    }
+
+    pub fn end_undo_group(&mut self) {
+        self.history.end_group();
+    }
+
+    pub fn break_undo_group(&mut self) {
+        self.history.break_group();
+    }
+
+    // Returns the char index where the cursor should go
+    pub fn undo(&mut self) -> Option<usize> {
+        let group = self.history.pop_undo()?;
+        let touches_line_endings = group.touches_line_endings();
+        let mut cursor = 0;
+        for edit in group.edits.iter().rev() {
+            cursor = match edit {
+                Edit::Insert { at, text } => {
+                    self.rope_remove(*at..*at + text.chars().count());
+                    *at
+                }
+                Edit::Delete { at, text } => {
+                    self.rope_insert(*at, text);
+                    *at + text.chars().count()
+                }
+            };
+        }
+        self.revision = next_revision();
+        if touches_line_endings {
+            self.refresh_line_endings();
+        }
+        Some(cursor)
+    }
+
+    pub fn redo(&mut self) -> Option<usize> {
+        let group = self.history.pop_redo()?;
+        let touches_line_endings = group.touches_line_endings();
+        let mut cursor = 0;
+        for edit in &group.edits {
+            cursor = match edit {
+                Edit::Insert { at, text } => {
+                    self.rope_insert(*at, text);
+                    *at + text.chars().count()
+                }
+                Edit::Delete { at, text } => {
+                    self.rope_remove(*at..*at + text.chars().count());
+                    *at
+                }
+            };
+        }
+        self.revision = next_revision();
+        if touches_line_endings {
+            self.refresh_line_endings();
+        }
+        Some(cursor)
+    }
+
+    // Writes the text in the document's encoding
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        self.encoding.write_rope(&self.rope, writer)
+    }
+
+    pub fn save_to(&self, path: impl AsRef<Path>, in_place: bool) -> io::Result<()> {
+        // Encode first so a character the encoding can't represent doesn't leave a truncated file
+        let mut bytes = Vec::with_capacity(self.rope.len_bytes());
+        self.write_to(&mut bytes)?;
+        if let Some(codec) = self.compression {
+            bytes = codec.compress(&bytes)?;
+        }
+        write_file(path.as_ref(), &bytes, in_place)
+    }
+
+    // Saves a partially loaded document: the text replaces the part of the file
+    // it was read from, and the bytes that were never loaded are copied over
+    // unchanged. Returns the new length of the replaced part.
+    pub fn save_spliced(&self, path: impl AsRef<Path>, tail_start: u64) -> io::Result<u64> {
+        let path = path.as_ref();
+        let mut bytes = Vec::with_capacity(self.rope.len_bytes());
+        self.write_to(&mut bytes)?;
+
+        let mut original = fs::File::open(path)?;
+        original.seek(SeekFrom::Start(tail_start))?;
+
+        // The tail is still being read from the original, so this can't be written in place
+        write_atomic(path, |out| {
+            out.write_all(&bytes)?;
+            io::copy(&mut original, out)?;
+            Ok(())
+        })?;
+        Ok(bytes.len() as u64)
+    }
+}
+
+impl std::fmt::Display for Document {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for chunk in self.rope.chunks() {
+            f.write_str(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl egui::TextBuffer for Document {
+    fn is_mutable(&self) -> bool {
+        true
+    }
+
+    fn as_str(&self) -> &str {
+        self.flat.get_or_init(|| self.rope.to_string())
+    }
+
+    fn insert_text(&mut self, text: &str, char_index: usize) -> usize {
+        // Enter inserts a bare LF, so new lines follow the document's style
+        let text = self.line_ending.apply_to_typed(text);
+        self.insert(char_index, &text);
+        text.chars().count()
+    }
+
+    fn delete_char_range(&mut self, char_range: Range<usize>) {
+        self.remove(char_range);
+    }
+
+    fn byte_index_from_char_index(&self, char_index: usize) -> usize {
+        self.rope.char_to_byte(char_index)
+    }
+
+    fn char_index_from_byte_index(&self, byte_index: usize) -> usize {
+        self.rope.byte_to_char(byte_index)
+    }
+
+    fn clear(&mut self) {
+        self.remove(0..self.rope.len_chars());
+    }
+
+    fn replace_with(&mut self, text: &str) {
+        self.replace_all(text);
+    }
+
+    fn take(&mut self) -> String {
+        let text = self.to_string();
+        self.clear();
+        text
+    }
+
+    fn type_id(&self) -> std::any::TypeId {
+        std::any::TypeId::of::<Self>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converting_line_endings_rewrites_the_text() {
+        for (text, from) in [("a\rb\rc", LineEnding::Cr), ("a\r\nb\r\nc", LineEnding::CrLf)] {
+            let mut document = Document::from_rope(Rope::from_str(text));
+            document.set_line_endings(&LineEndingStats::default());
+            document.convert_line_endings(LineEnding::Lf);
+            assert_eq!(document.to_string(), "a\nb\nc", "from {}", from.name());
+            assert_eq!(document.line_ending(), LineEnding::Lf);
+            // One step back to how it was
+            document.undo();
+            assert_eq!(document.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn converting_to_the_same_style_changes_nothing() {
+        let mut document = Document::from_rope(Rope::from_str("a\r\nb"));
+        document.convert_line_endings(LineEnding::CrLf);
+        assert_eq!(document.to_string(), "a\r\nb");
+        assert!(!document.can_undo());
+    }
+
+    #[test]
+    fn save_spliced_copies_the_unloaded_tail() {
+        let path = std::env::temp_dir().join(format!("amend-document-test-{}-spliced", std::process::id()));
+        let head = b"first line\nsecond line\n";
+        let tail: Vec<u8> = (0..=255u8).cycle().take(100_000).collect();
+        fs::write(&path, [&head[..], &tail].concat()).unwrap();
+
+        // The length is of the encoded text, which isn't the UTF-8 length here
+        let mut document = Document::from_rope(Rope::from_str("café\n"));
+        document.set_encoding(TextEncoding { encoding: encoding_rs::WINDOWS_1252, bom: false });
+        let written = document.save_spliced(&path, head.len() as u64);
+        let saved = fs::read(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(written.unwrap(), 5);
+        assert_eq!(saved.unwrap(), [&b"caf\xE9\n"[..], &tail].concat());
+    }
+}
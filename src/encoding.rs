@@ -0,0 +1,243 @@
+use std::io::{self, Write};
+use encoding_rs::{EncoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use ropey::Rope;
+
+// Encodings offered in the Save As and Reopen dialogs
+pub const SUPPORTED_ENCODINGS: &[&Encoding] = &[
+    UTF_8,
+    UTF_16LE,
+    UTF_16BE,
+    encoding_rs::WINDOWS_1252,
+    encoding_rs::ISO_8859_15,
+    encoding_rs::ISO_8859_2,
+    encoding_rs::WINDOWS_1250,
+    encoding_rs::WINDOWS_1251,
+    encoding_rs::KOI8_R,
+    encoding_rs::SHIFT_JIS,
+    encoding_rs::EUC_JP,
+    encoding_rs::GBK,
+    encoding_rs::GB18030,
+    encoding_rs::BIG5,
+    encoding_rs::EUC_KR,
+];
+
+// How many bytes from the start of a file are looked at to guess its encoding
+pub const SNIFF_LEN: usize = 64 * 1024;
+
+// The encoding a document was read in and will be written back in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextEncoding {
+    pub encoding: &'static Encoding,
+    pub bom: bool, // Whether the file starts with a byte order mark
+}
+
+impl Default for TextEncoding {
+    fn default() -> Self {
+        Self::UTF8
+    }
+}
+
+impl TextEncoding {
+    pub const UTF8: Self = Self {
+        encoding: UTF_8,
+        bom: false,
+    };
+
+    pub fn new(encoding: &'static Encoding) -> Self {
+        // UTF-16 is never written without a BOM, nothing could tell it apart later
+        let bom = encoding == UTF_16LE || encoding == UTF_16BE;
+        Self { encoding, bom }
+    }
+
+    pub fn name(&self) -> String {
+        if self.bom && self.encoding == UTF_8 {
+            "UTF-8 with BOM".to_string()
+        } else {
+            self.encoding.name().to_string()
+        }
+    }
+
+    pub fn bom_bytes(&self) -> &'static [u8] {
+        if !self.bom {
+            &[]
+        } else if self.encoding == UTF_8 {
+            b"\xEF\xBB\xBF"
+        } else if self.encoding == UTF_16LE {
+            b"\xFF\xFE"
+        } else if self.encoding == UTF_16BE {
+            b"\xFE\xFF"
+        } else {
+            &[]
+        }
+    }
+
+    // The char holding a byte offset into the file as written, BOM and all
+    pub fn char_at_offset(&self, rope: &Rope, offset: u64) -> usize {
+        let Some(mut remaining) = offset.checked_sub(self.bom_bytes().len() as u64) else {
+            return 0;
+        };
+        let clamp = |n: u64, len: usize| usize::try_from(n).unwrap_or(usize::MAX).min(len);
+        if self.encoding == UTF_8 {
+            return rope.byte_to_char(clamp(remaining, rope.len_bytes()));
+        }
+        if self.encoding == UTF_16LE || self.encoding == UTF_16BE {
+            return rope.utf16_cu_to_char(clamp(remaining / 2, rope.len_utf16_cu()));
+        }
+        if self.encoding.is_single_byte() {
+            return clamp(remaining, rope.len_chars());
+        }
+        // Multi-byte legacy encodings, some with escape sequences, so each
+        // character is encoded in turn
+        let mut encoder = self.encoding.new_encoder();
+        let mut buffer = [0u8; 16];
+        let mut utf8 = [0u8; 4];
+        for (i, c) in rope.chars().enumerate() {
+            let (_, _, written) = encoder.encode_from_utf8_without_replacement(c.encode_utf8(&mut utf8), &mut buffer, false);
+            if written as u64 > remaining {
+                return i;
+            }
+            remaining -= written as u64;
+        }
+        rope.len_chars()
+    }
+
+    // Writes the text in this encoding. Characters the encoding can't
+    // represent fail the write instead of being silently substituted.
+    pub fn write_rope<W: Write>(&self, rope: &Rope, mut writer: W) -> io::Result<()> {
+        writer.write_all(self.bom_bytes())?;
+
+        if self.encoding == UTF_8 {
+            return rope.write_to(writer);
+        }
+        if self.encoding == UTF_16LE || self.encoding == UTF_16BE {
+            let little_endian = self.encoding == UTF_16LE;
+            let mut buffer = Vec::new();
+            for chunk in rope.chunks() {
+                buffer.clear();
+                for unit in chunk.encode_utf16() {
+                    let bytes = if little_endian { unit.to_le_bytes() } else { unit.to_be_bytes() };
+                    buffer.extend_from_slice(&bytes);
+                }
+                writer.write_all(&buffer)?;
+            }
+            return Ok(());
+        }
+
+        let mut encoder = self.encoding.new_encoder();
+        let mut buffer = vec![0u8; 16 * 1024];
+        let mut chunks = rope.chunks().peekable();
+        while let Some(chunk) = chunks.next() {
+            let last = chunks.peek().is_none();
+            let mut input = chunk;
+            loop {
+                let (result, read, written) =
+                    encoder.encode_from_utf8_without_replacement(input, &mut buffer, last);
+                writer.write_all(&buffer[..written])?;
+                input = &input[read..];
+                match result {
+                    EncoderResult::InputEmpty => break,
+                    EncoderResult::OutputFull => continue,
+                    EncoderResult::Unmappable(c) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("'{}' (U+{:04X}) can't be saved as {}", c, c as u32, self.name()),
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Guesses the encoding of a file from its first bytes. Returns the encoding
+// and the length of the BOM to skip. `complete` says whether the sample is
+// the whole file, so a character cut off at the end isn't held against UTF-8.
+pub fn detect_encoding(sample: &[u8], complete: bool) -> (TextEncoding, usize) {
+    if let Some((encoding, bom_len)) = Encoding::for_bom(sample) {
+        return (TextEncoding { encoding, bom: true }, bom_len);
+    }
+
+    if let Some(encoding) = guess_utf16(sample) {
+        return (TextEncoding { encoding, bom: false }, 0);
+    }
+
+    match std::str::from_utf8(sample) {
+        Ok(_) => return (TextEncoding::UTF8, 0),
+        Err(e) if e.error_len().is_none() && !complete => return (TextEncoding::UTF8, 0),
+        Err(_) => {}
+    }
+
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(sample, complete);
+    let encoding = detector.guess(None, false);
+    (TextEncoding { encoding, bom: false }, 0)
+}
+
+// Text in UTF-16 without a BOM still gives itself away: mostly-ASCII content
+// has a zero in every other byte
+fn guess_utf16(sample: &[u8]) -> Option<&'static Encoding> {
+    let pairs = sample.len() / 2;
+    if pairs < 4 {
+        return None;
+    }
+    let even_zeros = sample.iter().step_by(2).filter(|b| **b == 0).count();
+    let odd_zeros = sample.iter().skip(1).step_by(2).filter(|b| **b == 0).count();
+    if odd_zeros * 10 > pairs * 4 && even_zeros * 10 < pairs {
+        Some(UTF_16LE)
+    } else if even_zeros * 10 > pairs * 4 && odd_zeros * 10 < pairs {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+// Binary files get the hex editor instead of being decoded as text. Text
+// rarely contains NULs or many control characters; UTF-16 is full of NULs, so
+// anything with a BOM or that looks like UTF-16 counts as text.
+pub fn looks_binary(sample: &[u8]) -> bool {
+    if sample.is_empty() || Encoding::for_bom(sample).is_some() || guess_utf16(sample).is_some() {
+        return false;
+    }
+    if memchr::memchr(0, sample).is_some() {
+        return true;
+    }
+    let control = sample
+        .iter()
+        .filter(|b| **b < 0x20 && !matches!(**b, b'\t' | b'\n' | b'\r' | 0x0C | 0x1B))
+        .count();
+    control * 10 > sample.len()
+}
+
+// Checks whether the bytes start with the BOM of the given encoding
+pub fn bom_len_for(encoding: &'static Encoding, sample: &[u8]) -> usize {
+    match Encoding::for_bom(sample) {
+        Some((bom_encoding, len)) if bom_encoding == encoding => len,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+
+    #[test]
+    fn offsets_count_bytes_as_written() {
+        let rope = Rope::from_str("aé€𝄞b");
+        assert_eq!(TextEncoding::UTF8.char_at_offset(&rope, 3), 2);
+        // Inside a character lands on it
+        assert_eq!(TextEncoding::UTF8.char_at_offset(&rope, 4), 2);
+        assert_eq!(TextEncoding { encoding: UTF_8, bom: true }.char_at_offset(&rope, 6), 2);
+        // The BOM itself is before the first character
+        assert_eq!(TextEncoding::new(UTF_16LE).char_at_offset(&rope, 1), 0);
+        // 2 bytes of BOM, then 2 per unit; the clef takes two units
+        assert_eq!(TextEncoding::new(UTF_16BE).char_at_offset(&rope, 8), 3);
+        assert_eq!(TextEncoding::new(UTF_16BE).char_at_offset(&rope, 12), 4);
+        assert_eq!(TextEncoding::new(WINDOWS_1252).char_at_offset(&rope, 2), 2);
+        assert_eq!(TextEncoding::new(WINDOWS_1252).char_at_offset(&rope, 100), 5);
+
+        let rope = Rope::from_str("aあb");
+        assert_eq!(TextEncoding::new(SHIFT_JIS).char_at_offset(&rope, 3), 2);
+    }
+}
@@ -0,0 +1,195 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use eframe::egui;
+use similar::ChangeTag;
+use crate::actions::{Action, Command};
+use crate::app::TextEditorApp;
+use crate::compression::decompress_if_compressed;
+use crate::diff_view::{diff_lines, show_diff};
+
+// What the file looked like when it was last loaded or saved. A different
+// stamp on disk means something else has written to it since.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    #[cfg(unix)]
+    inode: (u64, u64), // Device and inode, to find the file again after a rename
+}
+
+impl FileStamp {
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::from_metadata(&fs::metadata(path)?))
+    }
+
+    pub fn from_metadata(metadata: &fs::Metadata) -> Self {
+        Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            #[cfg(unix)]
+            inode: {
+                use std::os::unix::fs::MetadataExt;
+                (metadata.dev(), metadata.ino())
+            },
+        }
+    }
+
+    // Same contents as far as the metadata can tell
+    fn matches(&self, other: &FileStamp) -> bool {
+        self.modified == other.modified && self.len == other.len
+    }
+}
+
+enum DiskChange {
+    Modified,
+    Renamed(PathBuf),
+    Deleted,
+}
+
+// Looks for the file under another name in the same directory. Only possible
+// where files have an identity apart from their name.
+fn find_renamed(path: &Path, stamp: &FileStamp) -> Option<PathBuf> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        fs::read_dir(dir).ok()?.filter_map(|entry| entry.ok()).find_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            ((metadata.dev(), metadata.ino()) == stamp.inode && metadata.is_file()).then(|| entry.path())
+        })
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, stamp);
+        None
+    }
+}
+
+pub struct ExternalChangeDialog {
+    pub saving: bool, // Asked from Save; keeping our version saves it
+    pub diff: Option<Vec<(ChangeTag, String)>>,
+}
+
+impl TextEditorApp {
+    // Remembers the state of the open file after loading or saving it
+    pub fn remember_disk_state(&mut self) {
+        self.disk_stamp = self.filename.as_ref().and_then(|f| FileStamp::read(f).ok());
+    }
+
+    fn disk_change(&self) -> Option<DiskChange> {
+        let (filename, stamp) = (self.filename.as_ref()?, self.disk_stamp.as_ref()?);
+        match FileStamp::read(filename) {
+            Ok(current) if current.matches(stamp) => None,
+            Ok(_) => Some(DiskChange::Modified),
+            Err(_) => match find_renamed(Path::new(filename), stamp) {
+                Some(new_path) => Some(DiskChange::Renamed(new_path)),
+                None => Some(DiskChange::Deleted),
+            },
+        }
+    }
+
+    // Checks whether the open file was changed by something else. Returns
+    // false if the user has to decide first what to do about it.
+    pub fn check_disk(&mut self, saving: bool) -> bool {
+        if self.is_loading() || self.large_file.is_some() || self.external_change_dialog.is_some() {
+            return true;
+        }
+        let Some(change) = self.disk_change() else {
+            return true;
+        };
+        let filename = self.filename.clone().unwrap_or_default();
+        match change {
+            DiskChange::Modified if !self.has_unsaved_changes() && !saving => {
+                // Nothing of ours to lose
+                self.start_loading_file(filename);
+                true
+            }
+            DiskChange::Modified => {
+                self.external_change_dialog = Some(ExternalChangeDialog { saving, diff: None });
+                false
+            }
+            DiskChange::Renamed(new_path) => {
+                let new_name = new_path.display().to_string();
+                self.notifications.info(format!("{} was renamed to {}", filename, new_name));
+                self.filename = Some(new_name);
+                self.remember_disk_state();
+                true
+            }
+            DiskChange::Deleted => {
+                // Only say it once; saving writes the file again
+                self.disk_stamp = None;
+                if !saving {
+                    self.notifications.error(
+                        format!("{} was deleted or moved", filename),
+                        vec![
+                            ("Save".to_string(), self.in_this_tab(Command::Save)),
+                            ("Save As…".to_string(), self.in_this_tab(Command::SaveAs)),
+                        ],
+                    );
+                }
+                true
+            }
+        }
+    }
+
+    // The open file's text on disk, decoded the same way as the document
+    fn disk_text(&self) -> String {
+        let Some(bytes) = self.filename.as_ref().and_then(|path| fs::read(path).ok()) else {
+            return String::new();
+        };
+        let bytes = decompress_if_compressed(bytes);
+        let encoding = self.document.encoding();
+        let bytes = bytes.strip_prefix(encoding.bom_bytes()).unwrap_or(&bytes);
+        encoding.encoding.decode_without_bom_handling(bytes).0.into_owned()
+    }
+
+    pub fn show_external_change_dialog(&mut self, ctx: &egui::Context) {
+        let Some(dialog) = &mut self.external_change_dialog else {
+            return;
+        };
+        let name = self.filename.as_deref().unwrap_or_default();
+        let mut keep = false;
+        let mut reload = false;
+        let mut compare = false;
+
+        egui::Modal::new(egui::Id::new("external_change")).show(ctx, |ui| {
+            ui.heading("File Changed on Disk");
+            ui.label(format!("{} was changed by another program, and you have unsaved changes.", name));
+            ui.add_space(8.0);
+            ui.horizontal(|ui| {
+                let keep_label = if dialog.saving { "Keep Mine and Save" } else { "Keep Mine" };
+                keep = ui.button(keep_label).clicked();
+                reload = ui.button("Reload").on_hover_text("Discard your changes and load the file from disk").clicked();
+                compare = ui.add_enabled(self.hex_editor.is_none(), egui::Button::new("Compare")).clicked();
+            });
+            if let Some(diff) = &dialog.diff {
+                ui.separator();
+                ui.label("Your changes compared to the file on disk:");
+                show_diff(ui, diff);
+            }
+        });
+
+        if compare {
+            let diff = diff_lines(&self.disk_text(), &self.document.to_string());
+            if let Some(dialog) = &mut self.external_change_dialog {
+                dialog.diff = Some(diff);
+            }
+        } else if keep {
+            let saving = dialog.saving;
+            self.external_change_dialog = None;
+            // The next check compares against the file as it is now
+            self.remember_disk_state();
+            if saving {
+                self.save_file();
+            }
+        } else if reload {
+            self.external_change_dialog = None;
+            if let Some(filename) = self.filename.clone() {
+                self.discard_confirmed = true;
+                self.queue_action(Action::OpenFile(filename));
+            }
+        }
+    }
+}
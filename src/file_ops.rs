@@ -1,144 +1,207 @@
-use std::fs;
-use std::sync::Arc;
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::rc::Rc;
 use std::thread;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE};
+use ropey::{Rope, RopeBuilder};
+use crate::actions::{Action, Command};
 use crate::app::TextEditorApp;
+use crate::atomic_write::write_file;
+use crate::compression::{compress_with, detect_file, Codec, MAGIC_LEN};
+use crate::decoder::StreamDecoder;
+use crate::dialogs::SaveAsDialog;
+use crate::document::PartialLoad;
+use crate::encoding::{bom_len_for, detect_encoding, looks_binary, TextEncoding, SNIFF_LEN};
+use crate::external_change::FileStamp;
+use crate::hex_editor::HexEditor;
+use crate::large_file::{LargeFile, LARGE_FILE_THRESHOLD};
+use crate::line_ending::LineEndingStats;
+use crate::read_only::write_blocker;
+use crate::load_job::LoadJob;
+
+// Result of a background load, handed over to the UI thread
+pub struct LoadedFile {
+    pub filename: String,
+    pub content: Rope,
+    pub encoding: TextEncoding,
+    pub line_endings: LineEndingStats,
+    pub invalid_bytes: usize, // Bytes that were invalid in the encoding and became U+FFFD
+    pub partial: Option<PartialLoad>, // Set when only the start of the file was loaded
+    pub binary: Option<Vec<u8>>, // Raw bytes of a file that isn't text, for the hex editor
+    pub compression: Option<Codec>, // The file was compressed, and was decompressed while reading
+    pub write_error: Option<String>, // Why the file can't be written, found while loading
+    pub warning: Option<String>, // Something the user should know about what was loaded
+    pub stamp: FileStamp, // The file as it was when reading started
+}
+
+// Reads the start of the file to pick an encoding, and returns what it read
+// so the caller can carry on from there. Also says whether the file looks
+// binary; never when the user picked the encoding.
+fn sniff_encoding(source: &mut impl Read, forced: Option<&'static Encoding>) -> io::Result<(TextEncoding, bool, Vec<u8>)> {
+    let mut sample = Vec::with_capacity(SNIFF_LEN);
+    source.by_ref().take(SNIFF_LEN as u64).read_to_end(&mut sample)?;
+    let complete = sample.len() < SNIFF_LEN;
+    let encoding = match forced {
+        Some(encoding) => TextEncoding { encoding, bom: bom_len_for(encoding, &sample) > 0 },
+        None => detect_encoding(&sample, complete).0,
+    };
+    let binary = forced.is_none() && looks_binary(&sample);
+    Ok((encoding, binary, sample))
+}
+
+// Counts the bytes read from the file, which for a compressed file is fewer
+// than the bytes that come out of it
+struct CountingReader<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+// Index just past the last line break in a chunk that starts `offset` bytes into the file
+fn line_break_end(bytes: &[u8], encoding: &'static Encoding, offset: u64) -> Option<usize> {
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        let line_feed: [u8; 2] = if encoding == UTF_16LE { [0x0A, 0x00] } else { [0x00, 0x0A] };
+        // Code units start at even file offsets
+        let first = (offset % 2) as usize;
+        (first..bytes.len().saturating_sub(1))
+            .step_by(2)
+            .rev()
+            .find(|&i| bytes[i..i + 2] == line_feed)
+            .map(|i| i + 2)
+    } else {
+        // 0x0A is never part of a multi-byte character in the other supported encodings
+        memchr::memrchr(b'\n', bytes).map(|i| i + 1)
+    }
+}
 
 impl TextEditorApp {
     pub fn start_loading_file(&mut self, file_path: String) {
-        self.is_loading = true;
-        self.loading_filename = Some(file_path.clone());
-        
-        // Clear partial content and reset counters
-        if let Ok(mut partial) = self.partial_content.lock() {
-            *partial = String::new();
+        self.start_loading_file_with_encoding(file_path, None);
+    }
+    
+    // Loads the file, decoding it with the given encoding instead of guessing one
+    pub fn start_loading_file_with_encoding(&mut self, file_path: String, forced_encoding: Option<&'static Encoding>) {
+        // Files too big to edit open in the paged viewer instead. The viewer
+        // pages through the raw file, so compressed files are decompressed
+        // into the editor, up to as much text as the viewer would take over at.
+        let file_size = std::fs::metadata(&file_path).map(|m| m.len()).unwrap_or(0);
+        if file_size > LARGE_FILE_THRESHOLD && detect_file(&file_path).is_none() {
+            self.open_large_file(file_path);
+            return;
         }
-        if let Ok(mut bytes_loaded) = self.bytes_loaded.lock() {
-            *bytes_loaded = 0;
+        self.load_into_document(file_path, forced_encoding, false);
+    }
+    
+    // Loads only the first 10MB of a large file into the editor
+    pub fn start_loading_preview(&mut self, file_path: String) {
+        self.load_into_document(file_path, None, true);
+    }
+    
+    pub fn open_large_file(&mut self, file_path: String) {
+        match LargeFile::open(file_path.clone()) {
+            Ok(view) => {
+                self.new_file();
+                self.large_file = Some(view);
+            }
+            Err(error) if error.kind() == io::ErrorKind::Unsupported => {
+                // The editor reads UTF-16 fine, just not all of a file this big
+                self.notifications.info(format!("{} is UTF-16, which the large file viewer can't page through, so only its first 10 MB are open", file_path));
+                self.start_loading_preview(file_path);
+            }
+            Err(error) => self.load_job = Some(LoadJob::failed(file_path, error)),
         }
-        if let Ok(mut total_bytes) = self.total_bytes.lock() {
-            *total_bytes = 0;
+    }
+    
+    fn load_into_document(&mut self, file_path: String, forced_encoding: Option<&'static Encoding>, preview_only: bool) {
+        if let Some(job) = &self.load_job {
+            job.cancel();
         }
-        
-        // Spawn a thread to load the file asynchronously
-        let file_path_clone = file_path.clone();
-        let pending_content = Arc::clone(&self.pending_file_content);
-        let partial_content = Arc::clone(&self.partial_content);
-        let bytes_loaded = Arc::clone(&self.bytes_loaded);
-        let total_bytes = Arc::clone(&self.total_bytes);
+        let job = LoadJob::new(file_path.clone());
+        self.load_job = Some(job.clone());
         
         // Use a completely non-blocking approach
         thread::spawn(move || {
-            // Try to read the file size first
-            let file_size = std::fs::metadata(&file_path_clone)
-                .map(|m| m.len())
-                .unwrap_or(0);
-            
-            // Set total bytes
-            if let Ok(mut total) = total_bytes.lock() {
-                *total = file_size as usize;
-            }
-            
-            // For very large files, we might want to show a warning
-            if file_size > 100 * 1024 * 1024 { // 100MB
-                // For very large files, just read a preview
-                let mut contents = String::new();
-                if let Ok(mut file) = std::fs::File::open(&file_path_clone) {
-                    use std::io::Read;
-                    let mut buffer = [0; 512]; // Very small chunks
-                    let mut total_read = 0;
-                    let max_read = 10 * 1024 * 1024; // 10MB max
-                    
-                    loop {
-                        match file.read(&mut buffer) {
-                            Ok(0) => break, // EOF
-                            Ok(n) => {
-                                if let Ok(chunk) = std::str::from_utf8(&buffer[..n]) {
-                                    contents.push_str(chunk);
-                                    total_read += n;
-                                    
-                                    // Update partial content only for the first 2KB to fill the screen
-                                    if total_read <= 2048 && total_read % 512 < 256 { // First 2KB, every ~256 bytes
-                                        if let Ok(mut partial) = partial_content.lock() {
-                                            *partial = contents.clone();
-                                        }
-                                    }
-                                    
-                                    // Update bytes loaded every few chunks
-                                    if total_read % 4096 < 512 { // Every ~4KB
-                                        if let Ok(mut loaded) = bytes_loaded.lock() {
-                                            *loaded = total_read;
-                                        }
-                                    }
-                                    
-                                    if total_read >= max_read {
-                                        contents.push_str("\n\n... (file truncated - too large to display completely)");
-                                        // Set final bytes loaded
-                                        if let Ok(mut loaded) = bytes_loaded.lock() {
-                                            *loaded = total_read;
-                                        }
-                                        break;
-                                    }
-                                }
-                            }
-                            Err(_) => break,
-                        }
-                        
-                        // Yield very frequently
-                        std::thread::yield_now();
-                        std::thread::sleep(std::time::Duration::from_millis(2));
-                    }
-                }
-                
-                // Store the result in the shared state
-                if let Ok(mut pending) = pending_content.lock() {
-                    *pending = Some((file_path_clone, contents));
-                }
+            let result = read_file(&file_path, forced_encoding, preview_only, &job);
+            job.finish(result);
+        });
+    }
+    
+    // Takes over the result of the load job once it's done
+    pub fn poll_load_job(&mut self) {
+        let Some(job) = &self.load_job else {
+            return;
+        };
+        if let Some(preview) = job.take_preview() {
+            self.document.set_rope(preview);
+        }
+        if let Some(loaded) = job.take_loaded() {
+            self.document = self.new_document(loaded.content);
+            self.document.set_encoding(loaded.encoding);
+            self.document.set_line_endings(&loaded.line_endings);
+            self.document.set_partial(loaded.partial);
+            self.document.set_compression(loaded.compression);
+            // Reloading the same file keeps the user's choice to only view it
+            let keep_read_only = self.read_only && self.filename.as_ref() == Some(&loaded.filename);
+            self.read_only = keep_read_only || loaded.write_error.is_some() || loaded.warning.is_some();
+            self.write_error = loaded.write_error;
+            if let Some(warning) = loaded.warning {
+                self.notifications.info(warning);
+            }
+            self.add_recent(&loaded.filename);
+            self.filename = Some(loaded.filename);
+            self.large_file = None;
+            self.hex_editor = loaded.binary.map(HexEditor::new);
+            self.invalid_bytes = loaded.invalid_bytes;
+            self.disk_stamp = Some(loaded.stamp);
+            self.load_job = None;
+        } else if !job.is_active() && job.preview_shown() {
+            // The preview replaced the old text, and a half-loaded file mustn't
+            // be saved over the whole one. The tab stays on the file, empty and
+            // marked as partial, so saving it goes to another file.
+            let Some(job) = self.load_job.take() else {
+                return;
+            };
+            let file_size = job.progress().map_or(0, |(_, total)| total);
+            self.document = self.new_document(Rope::new());
+            if self.large_file.is_some() {
+                // Only the editable preview was being loaded; the viewer still has the file
+                self.load_job = Some(job).filter(|job| job.error().is_some());
+                return;
+            }
+            self.document.set_partial(Some(PartialLoad { loaded_bytes: 0, file_size, spliceable: false }));
+            self.hex_editor = None;
+            self.disk_stamp = None;
+            self.filename = Some(job.filename.clone());
+            if job.error().is_some() {
+                self.load_job = Some(job);
             } else {
-                // For smaller files, read normally but with frequent yields
-                let mut contents = String::new();
-                if let Ok(mut file) = std::fs::File::open(&file_path_clone) {
-                    use std::io::Read;
-                    let mut buffer = [0; 512]; // Small chunks
-                    let mut total_read = 0;
-                    
-                    loop {
-                        match file.read(&mut buffer) {
-                            Ok(0) => break, // EOF
-                            Ok(n) => {
-                                if let Ok(chunk) = std::str::from_utf8(&buffer[..n]) {
-                                    contents.push_str(chunk);
-                                    total_read += n;
-                                    
-                                    // Update partial content only for the first 2KB to fill the screen
-                                    if total_read <= 2048 && total_read % 512 < 256 { // First 2KB, every ~256 bytes
-                                        if let Ok(mut partial) = partial_content.lock() {
-                                            *partial = contents.clone();
-                                        }
-                                    }
-                                    
-                                    // Update bytes loaded every few chunks
-                                    if total_read % 2048 < 512 { // Every ~2KB
-                                        if let Ok(mut loaded) = bytes_loaded.lock() {
-                                            *loaded = total_read;
-                                        }
-                                    }
-                                }
-                            }
-                            Err(_) => break,
-                        }
-                        
-                        // Yield very frequently
-                        std::thread::yield_now();
-                        std::thread::sleep(std::time::Duration::from_millis(1));
-                    }
-                }
-                
-                // Store the result in the shared state
-                if let Ok(mut pending) = pending_content.lock() {
-                    *pending = Some((file_path_clone, contents));
-                }
+                self.notifications.error(
+                    format!("Loading {} was cancelled; nothing of it is loaded", job.filename),
+                    vec![("Load Again".to_string(), Action::OpenFile(job.filename))],
+                );
             }
-        });
+        } else if job.is_cancelled() {
+            self.load_job = None;
+        }
+    }
+    
+    pub fn is_loading(&self) -> bool {
+        self.load_job.as_ref().is_some_and(|job| job.is_active())
+    }
+    
+    pub fn cancel_loading(&mut self) {
+        if let Some(job) = &self.load_job {
+            job.cancel();
+        }
     }
     
     pub fn open_file(&mut self) {
@@ -148,30 +211,381 @@ impl TextEditorApp {
             .pick_file()
         {
             let file_path = path.display().to_string();
-            self.start_loading_file(file_path);
+            self.open_in_tab(file_path);
         }
     }
     
     pub fn save_file(&mut self) {
+        // The large file viewer is read-only
+        if self.large_file.is_some() {
+            return;
+        }
+        // Don't overwrite changes made by another program without asking
+        if !self.check_disk(true) {
+            return;
+        }
+        if !self.check_writable() {
+            return;
+        }
+        // Keep what's on disk now in the local history, unless this ends up as Save As
+        if self.document.partial().is_none_or(|partial| partial.spliceable) {
+            self.record_history();
+        }
+        if let (Some(hex_editor), Some(filename)) = (&mut self.hex_editor, &self.filename) {
+            // Binary files are written back byte for byte
+            let result = compress_with(self.document.compression(), &hex_editor.bytes)
+                .and_then(|bytes| write_file(Path::new(filename), &bytes, self.save_in_place));
+            match result {
+                Ok(()) => {
+                    hex_editor.modified = false;
+                    self.remember_disk_state();
+                }
+                Err(error) => self.report_save_error(&filename.clone(), &error),
+            }
+            return;
+        }
         if let Some(filename) = &self.filename {
-            if let Ok(()) = fs::write(filename, &self.text) {
-                self.is_modified = false;
+            match self.document.partial() {
+                Some(partial) if partial.spliceable => self.save_partial_spliced(&filename.clone(), partial),
+                Some(_) => {
+                    // Overwriting would cut the file short, so it can only be saved elsewhere
+                    self.save_file_as();
+                }
+                None => {
+                    match self.document.save_to(filename, self.save_in_place) {
+                        Ok(()) => {
+                            self.document.mark_saved();
+                            self.remember_disk_state();
+                        }
+                        Err(error) => self.report_save_error(&filename.clone(), &error),
+                    }
+                }
             }
         } else {
             self.save_file_as();
         }
     }
     
+    // Only the start of the file is loaded, so the rest of it is kept after
+    // the saved text
+    fn save_partial_spliced(&mut self, filename: &str, mut partial: PartialLoad) {
+        match self.document.save_spliced(filename, partial.loaded_bytes) {
+            Ok(loaded_bytes) => {
+                partial.file_size = partial.file_size - partial.loaded_bytes + loaded_bytes;
+                partial.loaded_bytes = loaded_bytes;
+                self.document.set_partial(Some(partial));
+                self.document.mark_saved();
+                self.remember_disk_state();
+            }
+            Err(error) => self.report_save_error(filename, &error),
+        }
+    }
+    
     pub fn save_file_as(&mut self) {
+        if self.large_file.is_some() {
+            return;
+        }
+        if self.hex_editor.is_some() {
+            // Encodings don't apply to binary files, go straight to the file picker
+            if let Some(path) = rfd::FileDialog::new().save_file() {
+                // Keep the file being replaced in its own history
+                if path.is_file() {
+                    self.record_history_of(&path.display().to_string());
+                }
+                let Some(hex_editor) = &mut self.hex_editor else {
+                    return;
+                };
+                // The new name decides whether it's compressed
+                let compression = Codec::from_extension(&path);
+                let result = compress_with(compression, &hex_editor.bytes)
+                    .and_then(|bytes| write_file(&path, &bytes, self.save_in_place));
+                match result {
+                    Ok(()) => {
+                        self.document.set_compression(compression);
+                        self.write_error = None;
+                        self.filename = Some(path.display().to_string());
+                        hex_editor.modified = false;
+                        self.remember_disk_state();
+                        self.add_recent(&path.display().to_string());
+                    }
+                    Err(error) => self.report_save_as_error(&path.display().to_string(), &error),
+                }
+            }
+            return;
+        }
+        // Ask for the encoding first, the dialog then calls save_file_as_with_encoding
+        let current = self.document.encoding();
+        self.save_as_dialog = Some(SaveAsDialog {
+            encoding: current.encoding,
+            bom: current.bom,
+        });
+    }
+    
+    pub fn save_file_as_with_encoding(&mut self, encoding: TextEncoding) {
         if let Some(path) = rfd::FileDialog::new()
             .add_filter("Text files", &["txt"])
             .add_filter("All files", &["*"])
             .save_file()
         {
-            if let Ok(()) = fs::write(&path, &self.text) {
-                self.filename = Some(path.display().to_string());
-                self.is_modified = false;
+            let partial = self.document.partial();
+            let same_file = self.filename.as_ref().is_some_and(|filename| is_same_file(filename, &path));
+            if let (Some(partial), true) = (partial, same_file) {
+                // Writing the loaded part over the file it came from would cut the file short
+                let unchanged = encoding == self.document.encoding() && Codec::from_extension(&path) == self.document.compression();
+                if partial.spliceable && unchanged {
+                    self.record_history_of(&path.display().to_string());
+                    self.save_partial_spliced(&path.display().to_string(), partial);
+                } else {
+                    self.notifications.error(
+                        format!("Only the first {} of {} bytes are loaded, so saving over {} would cut it short. Choose another file.",
+                            partial.loaded_bytes, partial.file_size, path.display()),
+                        vec![("Save As…".to_string(), self.in_this_tab(Command::SaveAs))],
+                    );
+                }
+                return;
+            }
+            // Keep the file being replaced in its own history
+            if path.is_file() {
+                self.record_history_of(&path.display().to_string());
+            }
+            let previous_encoding = self.document.encoding();
+            let previous_compression = self.document.compression();
+            self.document.set_encoding(encoding);
+            // The new name decides whether it's compressed
+            self.document.set_compression(Codec::from_extension(&path));
+            match self.document.save_to(&path, self.save_in_place) {
+                Ok(()) => {
+                    // The new file holds exactly what was loaded, so it's complete
+                    self.filename = Some(path.display().to_string());
+                    self.document.set_partial(None);
+                    self.document.mark_saved();
+                    self.write_error = None;
+                    self.remember_disk_state();
+                    self.add_recent(&path.display().to_string());
+                    if let Some(partial) = partial {
+                        self.notifications.info(format!("{} holds only the {} bytes that were loaded, not the whole {} bytes",
+                            path.display(), partial.loaded_bytes, partial.file_size));
+                    }
+                }
+                Err(error) => {
+                    self.document.set_encoding(previous_encoding);
+                    self.document.set_compression(previous_compression);
+                    self.report_save_as_error(&path.display().to_string(), &error);
+                }
             }
         }
     }
+    
+    fn report_save_error(&mut self, filename: &str, error: &io::Error) {
+        self.notifications.error(
+            format!("Couldn't save {}: {}", filename, error),
+            vec![
+                ("Retry".to_string(), self.in_this_tab(Command::Save)),
+                ("Save As…".to_string(), self.in_this_tab(Command::SaveAs)),
+            ],
+        );
+    }
+    
+    fn report_save_as_error(&mut self, filename: &str, error: &io::Error) {
+        self.notifications.error(
+            format!("Couldn't save {}: {}", filename, error),
+            vec![("Save As…".to_string(), self.in_this_tab(Command::SaveAs))],
+        );
+    }
+    
+    pub fn reopen_with_encoding(&mut self, encoding: &'static Encoding) {
+        if !self.confirm_discard(Action::ReopenWithEncoding(encoding)) {
+            return;
+        }
+        if let Some(filename) = self.filename.clone() {
+            self.start_loading_file_with_encoding(filename, Some(encoding));
+        }
+    }
+}
+
+// Whether both paths lead to the same file, following symlinks
+fn is_same_file(a: impl AsRef<Path>, b: impl AsRef<Path>) -> bool {
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+// Reads and decodes the whole file, or with `preview_only` just the first 10MB,
+// reporting progress to the job. Gives up early once the job is cancelled.
+// Compressed files stop at LARGE_FILE_THRESHOLD of text and open read-only.
+fn read_file(path: &str, forced_encoding: Option<&'static Encoding>, preview_only: bool, job: &LoadJob) -> io::Result<LoadedFile> {
+    let metadata = std::fs::metadata(path)?;
+    if metadata.is_dir() {
+        return Err(io::ErrorKind::IsADirectory.into());
+    }
+    let file_size = metadata.len();
+    let stamp = FileStamp::from_metadata(&metadata);
+    let write_error = write_blocker(path);
+    job.set_progress(0, file_size);
+    
+    let read_bytes = Rc::new(Cell::new(0));
+    let mut file = CountingReader { inner: File::open(path)?, count: Rc::clone(&read_bytes) };
+    let mut magic = Vec::with_capacity(MAGIC_LEN);
+    file.by_ref().take(MAGIC_LEN as u64).read_to_end(&mut magic)?;
+    let compression = Codec::detect(&magic);
+    let raw = io::Cursor::new(magic).chain(file);
+    let mut source: Box<dyn Read> = match compression {
+        Some(codec) => codec.decoder(raw)?,
+        None => Box::new(raw),
+    };
+    let (encoding, is_binary, mut sample) = sniff_encoding(&mut source, forced_encoding)?;
+    
+    // Someone asking for the first 10MB wants to edit it as text
+    if is_binary && !preview_only {
+        // The hex editor works on the raw bytes, BOM and all. It holds them
+        // all in memory, and a small compressed file can unpack to far more.
+        let mut bytes = sample;
+        let mut buffer = vec![0; 256 * 1024];
+        loop {
+            if job.is_cancelled() {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            let n = source.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            bytes.extend_from_slice(&buffer[..n]);
+            if bytes.len() as u64 > LARGE_FILE_THRESHOLD {
+                return Err(io::Error::other(format!("more than {} MB of binary data is too much for the hex editor",
+                    LARGE_FILE_THRESHOLD / (1024 * 1024))));
+            }
+            job.set_progress(read_bytes.get(), file_size);
+        }
+        return Ok(LoadedFile {
+            filename: path.to_string(),
+            content: Rope::new(),
+            encoding,
+            line_endings: LineEndingStats::default(),
+            invalid_bytes: 0,
+            partial: None,
+            binary: Some(bytes),
+            compression,
+            write_error,
+            warning: None,
+            stamp,
+        });
+    }
+    
+    let bom_len = encoding.bom_bytes().len() as u64;
+    sample.drain(..(bom_len as usize).min(sample.len()));
+    let mut source = io::Cursor::new(sample).chain(source);
+    let mut contents = RopeBuilder::new();
+    let mut preview = String::new();
+    let mut line_endings = LineEndingStats::default();
+    let mut partial = None;
+    let mut warning = None;
+    let mut decoder = StreamDecoder::new(encoding.encoding);
+    let mut decoded = String::new();
+    // Small chunks, except that decompressing is slow enough without them
+    let chunk_size = if compression.is_some() { 256 * 1024 } else { 512 };
+    let mut buffer = vec![0; chunk_size];
+    let mut total_read = 0;
+    let max_read = match (preview_only, compression) {
+        (true, _) => 10 * 1024 * 1024, // 10MB max for a preview
+        (false, Some(_)) => LARGE_FILE_THRESHOLD as usize,
+        (false, None) => usize::MAX,
+    };
+    let max_line_search = 1024 * 1024; // How far past the limit to look for a line break
+    
+    loop {
+        if job.is_cancelled() {
+            return Err(io::ErrorKind::Interrupted.into());
+        }
+        let n = source.read(&mut buffer)?;
+        if n == 0 { // EOF
+            decoded.clear();
+            decoder.finish(&mut decoded);
+            line_endings.feed(&decoded);
+            contents.append(&decoded);
+            break;
+        }
+        
+        // Past the limit, stop at the end of a line so that the rest of
+        // the file can be spliced back on untouched when saving
+        let read = n;
+        let mut n = n;
+        let mut stop = None;
+        if total_read + n >= max_read {
+            if let Some(end) = line_break_end(&buffer[..n], encoding.encoding, bom_len + total_read as u64) {
+                n = end;
+                stop = Some(true);
+            } else if total_read + n >= max_read + max_line_search {
+                // No line break in sight, the cut may split a character
+                stop = Some(false);
+            }
+        }
+        
+        decoded.clear();
+        decoder.decode(&buffer[..n], &mut decoded);
+        let chunk = decoded.as_str();
+        line_endings.feed(chunk);
+        contents.append(chunk);
+        total_read += n;
+        if total_read <= 4 * chunk_size {
+            preview.push_str(chunk);
+        }
+        
+        // Update partial content only for the first few chunks to fill the screen
+        if total_read <= 4 * chunk_size && total_read % chunk_size < chunk_size / 2 { // About every half chunk
+            job.set_preview(Rope::from_str(&preview));
+        }
+        
+        // Update bytes loaded every few chunks
+        if total_read % (4 * chunk_size) < chunk_size { // About every fourth chunk
+            job.set_progress(read_bytes.get(), file_size);
+        }
+        
+        if let Some(spliceable) = stop {
+            if compression.is_some() {
+                // The decompressed size isn't known, and the decoder reads
+                // ahead, so look for more text instead of comparing sizes
+                if n < read || source.read(&mut [0])? > 0 {
+                    partial = Some(PartialLoad {
+                        loaded_bytes: read_bytes.get().min(file_size),
+                        file_size,
+                        spliceable: false, // Only the start of a compressed stream can't be spliced back onto the rest
+                    });
+                    warning = Some(format!("Only the first {} MB of text in {} were decompressed, so it's open read-only",
+                        max_read / (1024 * 1024), path));
+                }
+                break;
+            }
+            let loaded_bytes = bom_len + total_read as u64;
+            if loaded_bytes < file_size {
+                partial = Some(PartialLoad {
+                    loaded_bytes,
+                    file_size,
+                    spliceable,
+                });
+            }
+            break;
+        }
+        
+        // Yield very frequently
+        if compression.is_none() {
+            std::thread::yield_now();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+    line_endings.finish();
+    
+    Ok(LoadedFile {
+        filename: path.to_string(),
+        content: contents.finish(),
+        encoding,
+        line_endings,
+        invalid_bytes: decoder.invalid_bytes(),
+        partial,
+        binary: None,
+        compression,
+        write_error,
+        warning,
+        stamp,
+    })
 }
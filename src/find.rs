@@ -0,0 +1,845 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use eframe::egui;
+use regex::bytes::{Regex, RegexBuilder};
+use similar::ChangeTag;
+use crate::app::TextEditorApp;
+use crate::diff_view::show_diff;
+
+// Matches past this many aren't highlighted in the editor, which would otherwise slow down drawing
+const MAX_HIGHLIGHTS: usize = 10_000;
+
+// Only this many matches are remembered for highlighting and stepping
+// through; beyond that they are only counted
+const MAX_MATCHES: usize = 100_000;
+
+const READ_BLOCK: usize = 4 * 1024 * 1024;
+
+// Longest regex match found across block boundaries in the large file viewer
+const MAX_REGEX_MATCH: usize = 64 * 1024;
+
+// Lines listed in the replace preview before the rest are only counted
+const MAX_PREVIEW: usize = 1000;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct FindOptions {
+    pub match_case: bool,
+    pub whole_word: bool,
+    pub regex: bool, // The query is a regular expression rather than plain text
+}
+
+// The query as a pattern, with the options that aren't regex flags applied
+fn pattern(query: &str, options: FindOptions) -> Result<String, regex::Error> {
+    let pattern = if options.regex {
+        // Checked on its own, so a stray ")" can't pair up with the group around it
+        regex::Regex::new(query)?;
+        format!("(?:{})", query)
+    } else {
+        regex::escape(query)
+    };
+    Ok(if options.whole_word { format!(r"\b{}\b", pattern) } else { pattern })
+}
+
+// For searching bytes, in the background
+pub fn build_regex(query: &str, options: FindOptions) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(&pattern(query, options)?)
+        .case_insensitive(!options.match_case)
+        .multi_line(true)
+        .crlf(true)
+        .build()
+}
+
+// The same for the document's text, for replacing
+pub fn build_text_regex(query: &str, options: FindOptions) -> Result<regex::Regex, regex::Error> {
+    regex::RegexBuilder::new(&pattern(query, options)?)
+        .case_insensitive(!options.match_case)
+        .multi_line(true)
+        .crlf(true)
+        .build()
+}
+
+// What a match is replaced with. With regular expressions on, "$1" and
+// "${name}" in the template stand for capture groups; otherwise it's used as it is.
+fn expand(captures: &regex::Captures, template: &str, options: FindOptions) -> String {
+    let mut replacement = String::new();
+    if options.regex {
+        captures.expand(template, &mut replacement);
+    } else {
+        replacement.push_str(template);
+    }
+    replacement
+}
+
+// Byte ranges of the first `limit` matches that lie within `within`, in
+// order, with what each is replaced by
+pub fn replacements(text: &str, regex: &regex::Regex, template: &str, options: FindOptions, within: Range<usize>, limit: usize) -> Vec<(Range<usize>, String)> {
+    let mut found = Vec::new();
+    for captures in regex.captures_iter(text) {
+        if found.len() == limit {
+            break;
+        }
+        let whole = captures.get(0).expect("group 0 is the whole match");
+        // Every match after this one ends past the range too
+        if whole.end() > within.end {
+            break;
+        }
+        if whole.start() >= within.start {
+            found.push((whole.range(), expand(&captures, template, options)));
+        }
+    }
+    found
+}
+
+// The lines the replacements touch, as they are and as they would be, for
+// the preview. Replacements that start on a line already shown for the one
+// before, i.e. the same line or one a multi-line match runs into, are shown together.
+pub fn preview_lines(text: &str, replacements: &[(Range<usize>, String)]) -> Vec<(ChangeTag, String)> {
+    let line_start = |at: usize| text[..at].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = |at: usize| text[at..].find('\n').map_or(text.len(), |i| at + i);
+    let mut lines = Vec::new();
+    let mut line = 1; // Number of the line at `counted`
+    let mut counted = 0;
+    let mut shown = 0;
+    let mut i = 0;
+    while i < replacements.len() {
+        if shown == MAX_PREVIEW {
+            lines.push((ChangeTag::Equal, format!("… and {} more", replacements.len() - i)));
+            break;
+        }
+        let start = line_start(replacements[i].0.start);
+        let mut end = line_end(replacements[i].0.end);
+        let mut j = i + 1;
+        while j < replacements.len() && replacements[j].0.start <= end {
+            end = line_end(replacements[j].0.end.max(end));
+            j += 1;
+        }
+
+        let mut after = String::new();
+        let mut pos = start;
+        for (range, replacement) in &replacements[i..j] {
+            after.push_str(&text[pos..range.start]);
+            after.push_str(replacement);
+            pos = range.end;
+        }
+        after.push_str(&text[pos..end]);
+
+        line += text[counted..start].bytes().filter(|&b| b == b'\n').count();
+        counted = start;
+        for (n, before) in text[start..end].split('\n').enumerate() {
+            lines.push((ChangeTag::Delete, format!("{}: {}", line + n, before.trim_end_matches('\r'))));
+        }
+        for (n, after) in after.split('\n').enumerate() {
+            lines.push((ChangeTag::Insert, format!("{}: {}", line + n, after.trim_end_matches('\r'))));
+        }
+        shown += 1;
+        i = j;
+    }
+    lines
+}
+
+#[derive(Default)]
+pub struct FindResults {
+    pub matches: Vec<(u64, usize)>, // Byte offset and length, in order
+    pub total: usize, // Can be more than matches.len()
+    pub scanned: u64,
+    pub done: bool,
+    pub error: Option<String>,
+}
+
+// Finds every match in a stream on a background thread. Results show up
+// while the search is still running.
+pub struct SearchJob {
+    results: Arc<Mutex<FindResults>>,
+    cancel: Arc<AtomicBool>,
+    pub size: u64, // Bytes to search, for progress
+}
+
+impl SearchJob {
+    pub fn start<F>(open: F, size: u64, regex: Regex, max_match_len: usize) -> Self
+    where
+        F: FnOnce() -> io::Result<Box<dyn Read>> + Send + 'static,
+    {
+        let results = Arc::new(Mutex::new(FindResults::default()));
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (results_clone, cancel_clone) = (Arc::clone(&results), Arc::clone(&cancel));
+        thread::spawn(move || {
+            let outcome = open().and_then(|source| scan(source, &regex, max_match_len, READ_BLOCK, &results_clone, &cancel_clone));
+            if let Ok(mut results) = results_clone.lock() {
+                if let Err(error) = outcome {
+                    results.error = Some(error.to_string());
+                }
+                results.done = true;
+            }
+        });
+        Self { results, cancel, size }
+    }
+
+    pub fn results(&self) -> Option<MutexGuard<'_, FindResults>> {
+        self.results.lock().ok()
+    }
+
+    pub fn shared_results(&self) -> Arc<Mutex<FindResults>> {
+        Arc::clone(&self.results)
+    }
+}
+
+impl Drop for SearchJob {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+// Reads the stream in blocks. The end of each block is kept for the next one,
+// so matches across the boundary are found, and one byte before the search
+// position is kept too, so word boundaries see what came before.
+fn scan(mut source: Box<dyn Read>, regex: &Regex, max_match_len: usize, block: usize, results: &Mutex<FindResults>, cancel: &AtomicBool) -> io::Result<()> {
+    let overlap = max_match_len;
+    let mut buffer = vec![0u8; block + overlap + 1];
+    let mut block_start = 0u64; // File offset of buffer[0]
+    let mut kept = 0;
+    let mut search_from = 0;
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let n = source.read(&mut buffer[kept..])?;
+        let filled = kept + n;
+        let at_end = n == 0;
+        // A match starting past the limit may run on into the next block. At
+        // the end, an empty match like "$" can start right after the last byte.
+        let limit = if at_end { filled + 1 } else { filled.saturating_sub(overlap) };
+
+        let mut found = Vec::new();
+        let mut at = search_from;
+        while at <= filled {
+            let Some(m) = regex.find_at(&buffer[..filled], at) else {
+                break;
+            };
+            if m.start() >= limit {
+                break;
+            }
+            found.push((block_start + m.start() as u64, m.len()));
+            at = if m.end() > m.start() { m.end() } else { m.start() + 1 };
+        }
+        if let Ok(mut results) = results.lock() {
+            results.total += found.len();
+            let room = MAX_MATCHES - results.matches.len();
+            results.matches.extend(found.into_iter().take(room));
+            results.scanned = block_start + limit.min(filled) as u64;
+        }
+        if at_end {
+            return Ok(());
+        }
+
+        let next_from = at.max(limit);
+        let carry_from = next_from.saturating_sub(1);
+        buffer.copy_within(carry_from..filled, 0);
+        kept = filled - carry_from;
+        search_from = next_from - carry_from;
+        block_start += carry_from as u64;
+    }
+}
+
+// The editor's text with the matches marked, for the text edit's layouter.
+// The matches may be from before the latest edit, so ranges that no longer
+// fit the text are skipped.
+pub fn highlight_job(ui: &egui::Ui, text: &str, results: &Mutex<FindResults>, wrap_width: f32) -> egui::text::LayoutJob {
+    let font = egui::TextStyle::Monospace.resolve(ui.style());
+    let color = ui.visuals().override_text_color.unwrap_or_else(|| ui.visuals().widgets.inactive.text_color());
+    let plain = egui::TextFormat::simple(font, color);
+    let mut marked = plain.clone();
+    marked.background = ui.visuals().warn_fg_color.gamma_multiply(0.35);
+
+    let mut job = egui::text::LayoutJob::default();
+    job.wrap.max_width = wrap_width;
+    let mut pos = 0;
+    if let Ok(results) = results.lock() {
+        for &(start, len) in results.matches.iter().take(MAX_HIGHLIGHTS) {
+            let (start, end) = (start as usize, start as usize + len);
+            if start < pos || end > text.len() || !text.is_char_boundary(start) || !text.is_char_boundary(end) {
+                continue;
+            }
+            job.append(&text[pos..start], 0.0, plain.clone());
+            job.append(&text[start..end], 0.0, marked.clone());
+            pos = end;
+        }
+    }
+    job.append(&text[pos..], 0.0, plain);
+    job
+}
+
+// What a search was run against; a different key means it's out of date
+#[derive(Clone, PartialEq, Eq)]
+struct SearchKey {
+    query: String,
+    options: FindOptions,
+    tab: u64,
+    revision: u64, // Document revision, or 0 for the large file viewer
+}
+
+pub struct FindBar {
+    pub query: String,
+    pub options: FindOptions,
+    pub current: Option<usize>, // Index of the selected match
+    pub focus: bool, // Put the keyboard in the query field next frame
+    pub show_replace: bool,
+    pub replacement: String,
+    pub show_preview: bool,
+    job: Option<SearchJob>,
+    key: Option<SearchKey>,
+    invalid: Option<String>, // Why the query isn't a valid regex
+    anchor: Option<u64>, // Jump to the first match at or after this byte once it's found
+    resume: Option<u64>, // Where to look for the next match after a replace
+    preview: Option<Preview>,
+}
+
+// Lines Replace All would change, for the search and template they were worked out for
+struct Preview {
+    key: SearchKey,
+    template: String,
+    lines: Vec<(ChangeTag, String)>,
+}
+
+impl FindBar {
+    fn new() -> Self {
+        Self {
+            query: String::new(),
+            options: FindOptions::default(),
+            current: None,
+            focus: true,
+            show_replace: false,
+            replacement: String::new(),
+            show_preview: false,
+            job: None,
+            key: None,
+            invalid: None,
+            anchor: None,
+            resume: None,
+            preview: None,
+        }
+    }
+
+    pub fn job(&self) -> Option<&SearchJob> {
+        self.job.as_ref()
+    }
+}
+
+impl TextEditorApp {
+    // Ctrl+F opens the bar for finding, Ctrl+H with the replace row too
+    pub fn open_find_bar(&mut self, ctx: &egui::Context, replace: bool) {
+        let selected = self.selected_line(ctx);
+        let bar = self.find_bar.get_or_insert_with(FindBar::new);
+        bar.focus = true;
+        bar.show_replace |= replace;
+        // Start from the selected text, as most editors do
+        if let Some(selected) = selected {
+            bar.query = selected;
+        }
+    }
+
+    // Chars selected in the text editor, if any
+    fn selection(&self, ctx: &egui::Context) -> Option<Range<usize>> {
+        if self.large_file.is_some() || self.hex_editor.is_some() {
+            return None;
+        }
+        let range = egui::TextEdit::load_state(ctx, self.editor_id())?.cursor.char_range()?;
+        let [start, end] = range.sorted_cursors().map(|c| c.index.min(self.document.len_chars()));
+        (start < end).then_some(start..end)
+    }
+
+    // The selection in the editor, if it's a short piece of a single line
+    fn selected_line(&self, ctx: &egui::Context) -> Option<String> {
+        let selection = self.selection(ctx).filter(|range| range.len() <= 200)?;
+        let selected = self.document.rope().slice(selection).to_string();
+        (!selected.contains('\n')).then_some(selected)
+    }
+
+    pub fn close_find_bar(&mut self) {
+        self.find_bar = None;
+        if let Some(large_file) = &mut self.large_file {
+            large_file.highlight = None;
+        }
+    }
+
+    // Starts a new search when the query, the options or the text changed,
+    // and follows the results of the running one
+    pub fn update_search(&mut self, ctx: &egui::Context) {
+        let tab = self.tabs[self.active_tab].id;
+        let cursor = self.cursor_byte(ctx);
+        let Some(bar) = &mut self.find_bar else {
+            return;
+        };
+        if self.hex_editor.is_some() || bar.query.is_empty() {
+            bar.job = None;
+            bar.key = None;
+            bar.invalid = None;
+            bar.current = None;
+            return;
+        }
+        let revision = if self.large_file.is_some() { 0 } else { self.document.revision() };
+        let key = SearchKey { query: bar.query.clone(), options: bar.options, tab, revision };
+        if bar.key.as_ref() != Some(&key) {
+            let editing = bar.key.as_ref().is_some_and(|old| old.query == key.query && old.options == key.options && old.tab == tab);
+            bar.key = Some(key);
+            bar.current = None;
+            bar.job = None;
+            let regex = match build_regex(&bar.query, bar.options) {
+                Ok(regex) => regex,
+                Err(error) => {
+                    bar.invalid = Some(error.to_string());
+                    return;
+                }
+            };
+            bar.invalid = None;
+            // Plain text can only match so many bytes; case folding can change the length
+            let max_match_len = if bar.options.regex { MAX_REGEX_MATCH } else { bar.query.len() * 4 + 16 };
+            bar.job = Some(if let Some(large_file) = &self.large_file {
+                let path = large_file.path.clone();
+                SearchJob::start(move || Ok(Box::new(File::open(path)?) as Box<dyn Read>), large_file.file_size, regex, max_match_len)
+            } else {
+                let rope = self.document.snapshot();
+                let size = rope.len_bytes();
+                // The whole document is in memory anyway, so a regex can match any of it
+                let max_match_len = if bar.options.regex { size } else { max_match_len };
+                SearchJob::start(move || Ok(Box::new(io::Cursor::new(rope.to_string().into_bytes())) as Box<dyn Read>), size as u64, regex, max_match_len)
+            });
+            // Typing in the document refreshes the matches without moving the cursor,
+            // replacing one match goes on to the next
+            bar.anchor = bar.resume.take().or((!editing).then_some(cursor));
+        }
+
+        let Some(job) = &bar.job else {
+            return;
+        };
+        let Some(results) = job.results() else {
+            return;
+        };
+        if !results.done {
+            ctx.request_repaint_after(std::time::Duration::from_millis(50));
+        }
+        let Some(anchor) = bar.anchor else {
+            return;
+        };
+        let next = results.matches.partition_point(|(start, _)| *start < anchor);
+        let jump = if next < results.matches.len() {
+            Some(next)
+        } else if results.done {
+            // Nothing after the cursor, wrap around to the first match
+            bar.anchor = None;
+            (!results.matches.is_empty()).then_some(0)
+        } else {
+            None
+        };
+        drop(results);
+        if let Some(index) = jump {
+            bar.anchor = None;
+            bar.current = Some(index);
+            self.reveal_match(index);
+        }
+    }
+
+    // Byte offset of the cursor, where a new search starts looking
+    fn cursor_byte(&self, ctx: &egui::Context) -> u64 {
+        if let Some(large_file) = &self.large_file {
+            return large_file.highlight.map_or(0, |(offset, _)| offset);
+        }
+        let cursor = egui::TextEdit::load_state(ctx, self.editor_id())
+            .and_then(|state| state.cursor.char_range())
+            .map_or(0, |range| range.sorted_cursors()[0].index);
+        let rope = self.document.rope();
+        rope.char_to_byte(cursor.min(rope.len_chars())) as u64
+    }
+
+    // F3 and Shift+F3, wrapping around at either end
+    pub fn find_next(&mut self, forward: bool) {
+        let Some(bar) = &mut self.find_bar else {
+            return;
+        };
+        let Some(count) = bar.job.as_ref().and_then(|job| job.results()).map(|r| r.matches.len()) else {
+            return;
+        };
+        if count == 0 {
+            return;
+        }
+        let index = match (bar.current, forward) {
+            (Some(i), true) => (i + 1) % count,
+            (Some(i), false) => (i + count - 1) % count,
+            (None, true) => 0,
+            (None, false) => count - 1,
+        };
+        bar.current = Some(index);
+        bar.anchor = None;
+        self.reveal_match(index);
+    }
+
+    // Selects the match and scrolls it into view
+    fn reveal_match(&mut self, index: usize) {
+        let Some((offset, len)) = self.find_bar.as_ref()
+            .and_then(|bar| bar.job.as_ref())
+            .and_then(|job| job.results())
+            .and_then(|results| results.matches.get(index).copied())
+        else {
+            return;
+        };
+        if let Some(large_file) = &mut self.large_file {
+            large_file.highlight = Some((offset, len));
+            if let Ok(line) = large_file.line_of_offset(offset) {
+                large_file.top_line = line.saturating_sub(3);
+            }
+            return;
+        }
+        let rope = self.document.rope();
+        let (start, end) = (offset as usize, offset as usize + len);
+        if end > rope.len_bytes() {
+            return;
+        }
+        self.view.select = Some((rope.byte_to_char(start), rope.byte_to_char(end)));
+    }
+}
+
+impl TextEditorApp {
+    // Replacing only changes the text editor's document
+    fn can_replace(&self) -> bool {
+        self.large_file.is_none() && self.hex_editor.is_none() && !self.read_only
+    }
+
+    // Byte edits turned into char edits for the document
+    fn char_edits(&self, edits: Vec<(Range<usize>, String)>) -> Vec<(Range<usize>, String)> {
+        let rope = self.document.rope();
+        edits.into_iter()
+            .map(|(range, text)| (rope.byte_to_char(range.start)..rope.byte_to_char(range.end), text))
+            .collect()
+    }
+
+    // Replaces the selected match and moves on to the next one. Without a
+    // selected match it only finds the next one, so the user sees what is replaced.
+    pub fn replace_current(&mut self) {
+        let Some(bar) = &self.find_bar else {
+            return;
+        };
+        if !self.can_replace() {
+            return;
+        }
+        let Some(index) = bar.current else {
+            self.find_next(true);
+            return;
+        };
+        let Some((offset, len)) = bar.job.as_ref()
+            .and_then(|job| job.results())
+            .and_then(|results| results.matches.get(index).copied())
+        else {
+            return;
+        };
+        let Ok(regex) = build_text_regex(&bar.query, bar.options) else {
+            return;
+        };
+        let text = egui::TextBuffer::as_str(&self.document);
+        let start = offset as usize;
+        if start > text.len() || !text.is_char_boundary(start) {
+            return;
+        }
+        // The match is found again to get its capture groups
+        let Some(captures) = regex.captures_at(text, start) else {
+            return;
+        };
+        let whole = captures.get(0).expect("group 0 is the whole match");
+        if whole.start() != start || whole.len() != len {
+            return;
+        }
+        let replacement = expand(&captures, &bar.replacement, bar.options);
+        let resume = start + replacement.len();
+        let edits = self.char_edits(vec![(whole.range(), replacement)]);
+        self.document.replace_ranges(&edits);
+        if let Some(bar) = &mut self.find_bar {
+            bar.resume = Some(resume as u64);
+        }
+    }
+
+    // Replaces every match in the document, or in the selection, as one undo step
+    pub fn replace_all(&mut self, ctx: &egui::Context, in_selection: bool) {
+        let Some(bar) = &self.find_bar else {
+            return;
+        };
+        if !self.can_replace() || bar.query.is_empty() {
+            return;
+        }
+        let Ok(regex) = build_text_regex(&bar.query, bar.options) else {
+            return;
+        };
+        let selection = self.selection(ctx);
+        let within = match (&selection, in_selection) {
+            (Some(chars), true) => {
+                let rope = self.document.rope();
+                rope.char_to_byte(chars.start)..rope.char_to_byte(chars.end)
+            }
+            (None, true) => {
+                self.notifications.info("Select the text to replace in first");
+                return;
+            }
+            (_, false) => 0..self.document.len_bytes(),
+        };
+        let text = egui::TextBuffer::as_str(&self.document);
+        let edits = replacements(text, &regex, &bar.replacement, bar.options, within, usize::MAX);
+        if edits.is_empty() {
+            self.notifications.info("Nothing to replace");
+            return;
+        }
+        let count = edits.len();
+        let edits = self.char_edits(edits);
+        // The selection grows or shrinks with what was replaced in it
+        let delta: isize = edits.iter().map(|(range, text)| text.chars().count() as isize - range.len() as isize).sum();
+        self.document.replace_ranges(&edits);
+        if let (Some(chars), true) = (selection, in_selection) {
+            self.view.select = Some((chars.start, chars.end.saturating_add_signed(delta)));
+        }
+        self.notifications.info(format!("Replaced {} {}", count, if count == 1 { "match" } else { "matches" }));
+    }
+
+    // Keeps the preview in step with the query, the template and the text
+    fn update_preview(&mut self) {
+        let tab = self.tabs[self.active_tab].id;
+        let Some(bar) = &mut self.find_bar else {
+            return;
+        };
+        if !bar.show_replace || !bar.show_preview || bar.query.is_empty() || self.large_file.is_some() || self.hex_editor.is_some() {
+            bar.preview = None;
+            return;
+        }
+        let key = SearchKey { query: bar.query.clone(), options: bar.options, tab, revision: self.document.revision() };
+        if bar.preview.as_ref().is_some_and(|preview| preview.key == key && preview.template == bar.replacement) {
+            return;
+        }
+        let lines = match build_text_regex(&bar.query, bar.options) {
+            Ok(regex) => {
+                // This runs on every key press, so stop looking once there's
+                // more than the preview shows rather than expand every match
+                let text = egui::TextBuffer::as_str(&self.document);
+                let mut edits = replacements(text, &regex, &bar.replacement, bar.options, 0..text.len(), MAX_PREVIEW + 1);
+                let more = edits.len() > MAX_PREVIEW;
+                edits.truncate(MAX_PREVIEW);
+                let mut lines = preview_lines(text, &edits);
+                if more {
+                    lines.push((ChangeTag::Equal, "… and more".to_string()));
+                }
+                lines
+            }
+            Err(_) => Vec::new(),
+        };
+        bar.preview = Some(Preview { key, template: bar.replacement.clone(), lines });
+    }
+
+    pub fn show_find_bar(&mut self, ctx: &egui::Context) {
+        self.update_preview();
+        let can_replace = self.can_replace();
+        let Some(bar) = &mut self.find_bar else {
+            return;
+        };
+        let mut step = None;
+        let mut replace = false;
+        let mut replace_all = None;
+        let mut close = false;
+        egui::TopBottomPanel::bottom("find_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Find:");
+                let response = ui.add(egui::TextEdit::singleline(&mut bar.query).desired_width(240.0).hint_text("Search"));
+                if std::mem::take(&mut bar.focus) {
+                    response.request_focus();
+                }
+                // Enter keeps the focus in the field for pressing it again
+                if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    step = Some(!ui.input(|i| i.modifiers.shift));
+                    response.request_focus();
+                }
+                if response.has_focus() && ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                    close = true;
+                }
+
+                if ui.add(egui::Button::selectable(bar.options.match_case, "Aa")).on_hover_text("Match case").clicked() {
+                    bar.options.match_case = !bar.options.match_case;
+                }
+                if ui.add(egui::Button::selectable(bar.options.whole_word, "W")).on_hover_text("Whole word").clicked() {
+                    bar.options.whole_word = !bar.options.whole_word;
+                }
+                if ui.add(egui::Button::selectable(bar.options.regex, ".*")).on_hover_text("Regular expression").clicked() {
+                    bar.options.regex = !bar.options.regex;
+                }
+                if ui.button("↑").on_hover_text("Previous match (Shift+F3)").clicked() {
+                    step = Some(false);
+                }
+                if ui.button("↓").on_hover_text("Next match (F3)").clicked() {
+                    step = Some(true);
+                }
+                if ui.add(egui::Button::selectable(bar.show_replace, "Replace…")).on_hover_text("Ctrl+H").clicked() {
+                    bar.show_replace = !bar.show_replace;
+                }
+
+                if self.hex_editor.is_some() {
+                    ui.label("Use the hex editor's own search for binary files");
+                } else if let Some(error) = &bar.invalid {
+                    ui.colored_label(ui.visuals().error_fg_color, "Invalid regular expression")
+                        .on_hover_text(egui::RichText::new(error).monospace());
+                } else if let Some(job) = &bar.job {
+                    if let Some(results) = job.results() {
+                        let status = if let Some(error) = &results.error {
+                            format!("Search failed: {}", error)
+                        } else if !results.done {
+                            let percent = (results.scanned * 100).checked_div(job.size).unwrap_or(100);
+                            format!("Searching… {}% ({} found)", percent.min(100), results.total)
+                        } else if results.total == 0 {
+                            "No results".to_string()
+                        } else {
+                            match bar.current {
+                                Some(current) => format!("{} of {}", current + 1, results.total),
+                                None => format!("{} matches", results.total),
+                            }
+                        };
+                        ui.label(status);
+                        if results.total > results.matches.len() {
+                            ui.label("(only the first matches can be stepped through)");
+                        }
+                    }
+                }
+
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    if ui.small_button("×").on_hover_text("Close (Esc)").clicked() {
+                        close = true;
+                    }
+                });
+            });
+
+            if !bar.show_replace {
+                return;
+            }
+            ui.horizontal(|ui| {
+                ui.label("Replace:");
+                let hint = if bar.options.regex { "$1 or ${name} for groups" } else { "Replacement" };
+                let response = ui.add(egui::TextEdit::singleline(&mut bar.replacement).desired_width(240.0).hint_text(hint));
+                if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    replace = true;
+                    response.request_focus();
+                }
+                let enabled = can_replace && bar.invalid.is_none() && !bar.query.is_empty();
+                if ui.add_enabled(enabled, egui::Button::new("Replace"))
+                    .on_hover_text("Replace the selected match and go to the next one")
+                    .clicked()
+                {
+                    replace = true;
+                }
+                if ui.add_enabled(enabled, egui::Button::new("Replace All")).clicked() {
+                    replace_all = Some(false);
+                }
+                if ui.add_enabled(enabled, egui::Button::new("In Selection"))
+                    .on_hover_text("Replace every match inside the selected text")
+                    .clicked()
+                {
+                    replace_all = Some(true);
+                }
+                ui.checkbox(&mut bar.show_preview, "Preview");
+                if !can_replace && self.hex_editor.is_none() {
+                    ui.label("The document can't be changed");
+                }
+            });
+            if let Some(preview) = &bar.preview {
+                ui.label("Lines Replace All would change:");
+                show_diff(ui, &preview.lines);
+            }
+        });
+
+        if close {
+            self.close_find_bar();
+        } else if let Some(forward) = step {
+            self.find_next(forward);
+        } else if replace {
+            self.replace_current();
+        } else if let Some(in_selection) = replace_all {
+            self.replace_all(ctx, in_selection);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Everything the background search finds, reading the text `block` bytes at a time
+    fn scan_all(text: &str, query: &str, options: FindOptions, block: usize) -> Vec<(u64, usize)> {
+        let regex = build_regex(query, options).unwrap();
+        let max_match_len = if options.regex { 8 } else { query.len() * 4 + 16 };
+        let results = Mutex::new(FindResults::default());
+        let source = Box::new(io::Cursor::new(text.as_bytes().to_vec()));
+        scan(source, &regex, max_match_len, block, &results, &AtomicBool::new(false)).unwrap();
+        results.into_inner().unwrap().matches
+    }
+
+    fn replace(text: &str, query: &str, template: &str, options: FindOptions, within: Range<usize>) -> Vec<(Range<usize>, String)> {
+        let regex = build_text_regex(query, options).unwrap();
+        replacements(text, &regex, template, options, within, usize::MAX)
+    }
+
+    #[test]
+    fn templates_expand_groups_only_with_regex() {
+        let options = FindOptions { regex: true, ..FindOptions::default() };
+        assert_eq!(replace("a=1, b=2", r"(\w)=(\d)", "$2=$1", options, 0..8), [(0..3, "1=a".to_string()), (5..8, "2=b".to_string())]);
+        assert_eq!(replace("a=1", r"(?P<key>\w)=(?P<value>\d)", "${value}${key}", options, 0..3), [(0..3, "1a".to_string())]);
+        // Off, the query and the template are both taken as they are
+        let literal = FindOptions::default();
+        assert_eq!(replace("a.b axb", "a.b", "$1", literal, 0..7), [(0..3, "$1".to_string())]);
+    }
+
+    #[test]
+    fn replacements_stay_within_the_selection() {
+        let text = "cat cat cat cat";
+        let found = replace(text, "cat", "dog", FindOptions::default(), 2..10);
+        // The first match starts before the selection and the last ends after it
+        assert_eq!(found, [(4..7, "dog".to_string())]);
+        assert_eq!(replace(text, "cat", "dog", FindOptions::default(), 4..11), [(4..7, "dog".to_string()), (8..11, "dog".to_string())]);
+    }
+
+    #[test]
+    fn preview_groups_matches_on_the_same_line() {
+        let text = "a a\nb\na";
+        let found = replace(text, "a", "x", FindOptions::default(), 0..text.len());
+        assert_eq!(preview_lines(text, &found), [
+            (ChangeTag::Delete, "1: a a".to_string()),
+            (ChangeTag::Insert, "1: x x".to_string()),
+            (ChangeTag::Delete, "3: a".to_string()),
+            (ChangeTag::Insert, "3: x".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn matches_across_block_edges() {
+        let text = "needle haystack needleneedle hay\nneedle";
+        let expected: Vec<(u64, usize)> = build_regex("needle", FindOptions::default()).unwrap()
+            .find_iter(text.as_bytes())
+            .map(|m| (m.start() as u64, m.len()))
+            .collect();
+        assert_eq!(expected.len(), 4);
+        for block in 1..=text.len() + 1 {
+            assert_eq!(scan_all(text, "needle", FindOptions::default(), block), expected, "block of {}", block);
+        }
+    }
+
+    #[test]
+    fn zero_width_matches() {
+        let options = FindOptions { regex: true, ..FindOptions::default() };
+        for block in 1..=8 {
+            assert_eq!(scan_all("ab\ncd\n", "^", options, block), [(0, 0), (3, 0), (6, 0)], "block of {}", block);
+            assert_eq!(scan_all("ab\ncd", "$", options, block), [(2, 0), (5, 0)], "block of {}", block);
+            assert_eq!(scan_all("ab cd", r"\b", options, block), [(0, 0), (2, 0), (3, 0), (5, 0)], "block of {}", block);
+        }
+    }
+
+    #[test]
+    fn whole_words_at_block_edges() {
+        let options = FindOptions { whole_word: true, ..FindOptions::default() };
+        let text = "word xword wordx word";
+        for block in 1..=text.len() + 1 {
+            assert_eq!(scan_all(text, "word", options, block), [(0, 4), (17, 4)], "block of {}", block);
+        }
+    }
+}
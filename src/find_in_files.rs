@@ -0,0 +1,446 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use eframe::egui;
+use encoding_rs::Encoding;
+use ignore::overrides::OverrideBuilder;
+use ignore::WalkBuilder;
+use regex::Regex;
+use crate::actions::Action;
+use crate::app::TextEditorApp;
+use crate::find::{build_text_regex, FindOptions};
+use crate::replace_in_files::{show_review, ReplaceReview};
+
+// Lines shown above and below each match
+const CONTEXT_LINES: usize = 2;
+
+// The search stops after this many matches, which is also about as many as
+// the panel can show without slowing down
+pub const MAX_HITS: usize = 2_000;
+
+// Bigger files are skipped, like binary ones
+const MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+// How far into a file to look for NUL bytes, which mark it as binary
+const BINARY_CHECK_LEN: usize = 8192;
+
+pub struct Hit {
+    pub line: usize, // 1-based
+    pub column: usize, // 1-based, in chars
+    pub text: String, // The line the match starts on
+    pub range: Range<usize>, // Bytes of `text` that matched
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+pub struct FileHits {
+    pub path: PathBuf,
+    pub hits: Vec<Hit>,
+}
+
+#[derive(Default)]
+pub struct FilesResults {
+    pub files: Vec<FileHits>, // In the order they were searched
+    pub searched: usize,
+    pub hits: usize,
+    pub skipped: usize, // Binary, too big or unreadable
+    pub truncated: bool, // Stopped at MAX_HITS
+    pub done: bool,
+}
+
+// Lines without their line break
+fn line_text(text: &str, lines: &[usize], index: usize) -> String {
+    let end = lines.get(index + 1).copied().unwrap_or(text.len());
+    text[lines[index]..end].trim_end_matches(['\n', '\r']).to_string()
+}
+
+// Every match in the text with its position and the lines around it
+pub fn search_text(text: &str, regex: &Regex, limit: usize) -> Vec<Hit> {
+    let mut lines = vec![0];
+    lines.extend(text.match_indices('\n').map(|(i, _)| i + 1).filter(|&i| i < text.len()));
+    let mut hits = Vec::new();
+    for found in regex.find_iter(text).take(limit) {
+        let index = lines.partition_point(|&start| start <= found.start()) - 1;
+        let start = lines[index];
+        let line = line_text(text, &lines, index);
+        let end = (found.end() - start).min(line.len());
+        hits.push(Hit {
+            line: index + 1,
+            column: text[start..found.start()].chars().count() + 1,
+            range: (found.start() - start).min(end)..end,
+            before: (index.saturating_sub(CONTEXT_LINES)..index).map(|i| line_text(text, &lines, i)).collect(),
+            after: (index + 1..(index + 1 + CONTEXT_LINES).min(lines.len())).map(|i| line_text(text, &lines, i)).collect(),
+            text: line,
+        });
+    }
+    hits
+}
+
+// The file as text, or None if it's binary or too big to search. Files
+// with a byte order mark are decoded by it; others are read as UTF-8.
+pub fn read_text(path: &Path) -> Option<String> {
+    if fs::metadata(path).ok()?.len() > MAX_FILE_SIZE {
+        return None;
+    }
+    let bytes = fs::read(path).ok()?;
+    if let Some((encoding, bom_len)) = Encoding::for_bom(&bytes) {
+        return Some(encoding.decode_without_bom_handling(&bytes[bom_len..]).0.into_owned());
+    }
+    if bytes[..bytes.len().min(BINARY_CHECK_LEN)].contains(&0) {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn globs(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').map(str::trim).filter(|glob| !glob.is_empty())
+}
+
+// Walks the folder the way ripgrep does: .gitignore and .ignore files are
+// respected and hidden files skipped. Include globs limit the search to
+// matching files, exclude globs leave matching files out.
+pub fn walker(root: &Path, include: &str, exclude: &str) -> Result<WalkBuilder, ignore::Error> {
+    let mut overrides = OverrideBuilder::new(root);
+    for glob in globs(include) {
+        overrides.add(glob)?;
+    }
+    for glob in globs(exclude) {
+        overrides.add(&format!("!{}", glob))?;
+    }
+    let mut builder = WalkBuilder::new(root);
+    builder.overrides(overrides.build()?);
+    Ok(builder)
+}
+
+// Searches the files on a background thread. Results show up file by file.
+pub struct FilesSearch {
+    results: Arc<Mutex<FilesResults>>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl FilesSearch {
+    pub fn start(walker: WalkBuilder, regex: Regex) -> Self {
+        let results = Arc::new(Mutex::new(FilesResults::default()));
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (results_clone, cancel_clone) = (Arc::clone(&results), Arc::clone(&cancel));
+        thread::spawn(move || {
+            let mut found = 0;
+            for entry in walker.build() {
+                if cancel_clone.load(Ordering::Relaxed) {
+                    break;
+                }
+                let Ok(entry) = entry else {
+                    continue;
+                };
+                if !entry.file_type().is_some_and(|kind| kind.is_file()) {
+                    continue;
+                }
+                let path = entry.into_path();
+                let text = read_text(&path);
+                let hits = text.as_deref().map_or_else(Vec::new, |text| search_text(text, &regex, MAX_HITS - found));
+                found += hits.len();
+                let Ok(mut results) = results_clone.lock() else {
+                    return;
+                };
+                results.searched += 1;
+                results.skipped += usize::from(text.is_none());
+                results.hits = found;
+                if !hits.is_empty() {
+                    results.files.push(FileHits { path, hits });
+                }
+                if found >= MAX_HITS {
+                    results.truncated = true;
+                    break;
+                }
+            }
+            if let Ok(mut results) = results_clone.lock() {
+                results.done = true;
+            }
+        });
+        Self { results, cancel }
+    }
+
+    pub fn results(&self) -> Option<MutexGuard<'_, FilesResults>> {
+        self.results.lock().ok()
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for FilesSearch {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+pub struct FilesPanel {
+    pub root: String, // Folder to search
+    pub query: String,
+    pub options: FindOptions,
+    pub include: String, // Comma separated globs, e.g. "*.rs, *.toml"
+    pub exclude: String,
+    pub focus: bool, // Put the keyboard in the query field next frame
+    pub error: Option<String>, // Why the last search couldn't start
+    pub search: Option<FilesSearch>,
+    pub searched_root: PathBuf, // Results are shown relative to this
+    pub show_replace: bool,
+    pub replacement: String,
+    pub review: Option<ReplaceReview>, // Shown instead of the results while replacements are gone through
+}
+
+impl FilesPanel {
+    fn new(root: String) -> Self {
+        Self {
+            root,
+            query: String::new(),
+            options: FindOptions::default(),
+            include: String::new(),
+            exclude: String::new(),
+            focus: true,
+            error: None,
+            search: None,
+            searched_root: PathBuf::new(),
+            show_replace: false,
+            replacement: String::new(),
+            review: None,
+        }
+    }
+
+    pub fn start_search(&mut self) {
+        self.search = None;
+        self.review = None;
+        self.error = None;
+        if self.query.is_empty() {
+            return;
+        }
+        let root = match fs::canonicalize(self.root.trim()) {
+            Ok(root) if root.is_dir() => root,
+            Ok(_) => {
+                self.error = Some("Not a folder".to_string());
+                return;
+            }
+            Err(error) => {
+                self.error = Some(format!("Can't open the folder: {}", error));
+                return;
+            }
+        };
+        let regex = match build_text_regex(&self.query, self.options) {
+            Ok(regex) => regex,
+            Err(error) => {
+                self.error = Some(error.to_string());
+                return;
+            }
+        };
+        match walker(&root, &self.include, &self.exclude) {
+            Ok(walker) => self.search = Some(FilesSearch::start(walker, regex)),
+            Err(error) => self.error = Some(format!("Invalid glob: {}", error)),
+        }
+        self.searched_root = root;
+    }
+}
+
+// A result line with the match marked
+fn hit_job(ui: &egui::Ui, hit: &Hit) -> egui::text::LayoutJob {
+    let font = egui::TextStyle::Monospace.resolve(ui.style());
+    let color = ui.visuals().text_color();
+    let plain = egui::TextFormat::simple(font, color);
+    let mut marked = plain.clone();
+    marked.background = ui.visuals().warn_fg_color.gamma_multiply(0.35);
+    let mut job = egui::text::LayoutJob::default();
+    job.append(&format!("{:>5}: ", hit.line), 0.0, plain.clone());
+    job.append(&hit.text[..hit.range.start], 0.0, plain.clone());
+    job.append(&hit.text[hit.range.clone()], 0.0, marked);
+    job.append(&hit.text[hit.range.end..], 0.0, plain);
+    job
+}
+
+impl TextEditorApp {
+    // Ctrl+Shift+F, starting in the folder of the file in front
+    pub fn toggle_files_panel(&mut self) {
+        if self.files_panel.is_some() {
+            self.files_panel = None;
+            return;
+        }
+        let root = self.filename.as_deref()
+            .and_then(|filename| Path::new(filename).parent())
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::current_dir().ok())
+            .map_or_else(String::new, |dir| dir.display().to_string());
+        self.files_panel = Some(FilesPanel::new(root));
+    }
+
+    // Shows the line in the tab it's open in, or opens the file first
+    pub fn open_at(&mut self, path: String, line: usize, column: usize) {
+        if self.tab_with_file(&path) != Some(self.active_tab) {
+            self.queue_action(Action::OpenFile(path));
+        }
+        self.queue_action(Action::GotoLine { line, column: Some(column) });
+    }
+
+    pub fn show_files_panel(&mut self, ctx: &egui::Context) {
+        let Some(panel) = &mut self.files_panel else {
+            return;
+        };
+        let can_undo = !self.replaced_in_files.is_empty();
+        let mut open = None;
+        let mut close = false;
+        let mut review = false;
+        let mut apply = false;
+        let mut undo = false;
+        egui::SidePanel::left("find_in_files").default_width(420.0).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.heading("Find in Files");
+                close = ui.small_button("Close").clicked();
+            });
+            let mut start = false;
+            egui::Grid::new("find_in_files_form").num_columns(2).show(ui, |ui| {
+                ui.label("Find:");
+                ui.horizontal(|ui| {
+                    let response = ui.add(egui::TextEdit::singleline(&mut panel.query).desired_width(200.0));
+                    if std::mem::take(&mut panel.focus) {
+                        response.request_focus();
+                    }
+                    start |= response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    let options = &mut panel.options;
+                    if ui.add(egui::Button::selectable(options.match_case, "Aa")).on_hover_text("Match case").clicked() {
+                        options.match_case = !options.match_case;
+                    }
+                    if ui.add(egui::Button::selectable(options.whole_word, "W")).on_hover_text("Whole word").clicked() {
+                        options.whole_word = !options.whole_word;
+                    }
+                    if ui.add(egui::Button::selectable(options.regex, ".*")).on_hover_text("Regular expression").clicked() {
+                        options.regex = !options.regex;
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Folder:");
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut panel.root).desired_width(200.0));
+                    if ui.button("Browse…").clicked() {
+                        if let Some(dir) = rfd::FileDialog::new().set_directory(&panel.root).pick_folder() {
+                            panel.root = dir.display().to_string();
+                        }
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Include:");
+                ui.add(egui::TextEdit::singleline(&mut panel.include).desired_width(200.0).hint_text("*.rs, src/**"));
+                ui.end_row();
+                ui.label("Exclude:");
+                ui.add(egui::TextEdit::singleline(&mut panel.exclude).desired_width(200.0).hint_text("*.min.js, target/**"));
+                ui.end_row();
+            });
+
+            let running = panel.search.as_ref().and_then(|search| search.results()).is_some_and(|results| !results.done);
+            ui.horizontal(|ui| {
+                start |= ui.add_enabled(!panel.query.is_empty(), egui::Button::new("Search")).clicked();
+                if ui.add_enabled(running, egui::Button::new("Stop")).clicked() {
+                    if let Some(search) = &panel.search {
+                        search.cancel();
+                    }
+                }
+                if ui.add(egui::Button::selectable(panel.show_replace, "Replace…")).clicked() {
+                    panel.show_replace = !panel.show_replace;
+                }
+            });
+            if panel.show_replace {
+                ui.horizontal(|ui| {
+                    let hint = if panel.options.regex { "$1 or ${name} for groups" } else { "Replacement" };
+                    ui.add(egui::TextEdit::singleline(&mut panel.replacement).desired_width(200.0).hint_text(hint));
+                    let has_results = panel.search.as_ref()
+                        .and_then(|search| search.results())
+                        .is_some_and(|results| results.done && !results.files.is_empty());
+                    review = ui.add_enabled(has_results, egui::Button::new("Review…"))
+                        .on_hover_text("Go through the replacements file by file before making them")
+                        .clicked();
+                });
+            }
+            if ui.add_enabled(can_undo, egui::Button::new("Undo Replace in Files")).clicked() {
+                undo = true;
+            }
+            ui.label(egui::RichText::new("Files listed in .gitignore and hidden files are skipped").weak());
+            if start {
+                panel.start_search();
+            }
+            if let Some(error) = &panel.error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+            ui.separator();
+
+            if let Some(replace_review) = &mut panel.review {
+                match show_review(ui, replace_review) {
+                    Some(true) => apply = true,
+                    Some(false) => panel.review = None,
+                    None => {}
+                }
+                return;
+            }
+
+            let Some(results) = panel.search.as_ref().and_then(|search| search.results()) else {
+                return;
+            };
+            let mut status = format!("{} matches in {} of {} files", results.hits, results.files.len(), results.searched);
+            if !results.done {
+                status.insert_str(0, "Searching… ");
+                ctx.request_repaint_after(std::time::Duration::from_millis(100));
+            }
+            if results.skipped > 0 {
+                status.push_str(&format!(", {} binary or too big", results.skipped));
+            }
+            ui.label(status);
+            if results.truncated {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("Stopped after {} matches", MAX_HITS));
+            }
+
+            egui::ScrollArea::both().id_salt("find_in_files_results").auto_shrink([false, false]).show(ui, |ui| {
+                let weak = ui.visuals().weak_text_color();
+                for file in &results.files {
+                    let name = file.path.strip_prefix(&panel.searched_root).unwrap_or(&file.path);
+                    egui::CollapsingHeader::new(format!("{} ({})", name.display(), file.hits.len()))
+                        .id_salt(&file.path)
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            for (i, hit) in file.hits.iter().enumerate() {
+                                if i > 0 {
+                                    ui.separator();
+                                }
+                                for (n, line) in hit.before.iter().enumerate() {
+                                    let number = hit.line - hit.before.len() + n;
+                                    ui.label(egui::RichText::new(format!("{:>5}  {}", number, line)).monospace().color(weak));
+                                }
+                                let response = ui.add(egui::Label::new(hit_job(ui, hit)).sense(egui::Sense::click()))
+                                    .on_hover_text(format!("Open at line {}, column {}", hit.line, hit.column));
+                                if response.clicked() {
+                                    open = Some((file.path.display().to_string(), hit.line, hit.column));
+                                }
+                                for (n, line) in hit.after.iter().enumerate() {
+                                    let number = hit.line + 1 + n;
+                                    ui.label(egui::RichText::new(format!("{:>5}  {}", number, line)).monospace().color(weak));
+                                }
+                            }
+                        });
+                }
+            });
+        });
+
+        if close {
+            self.files_panel = None;
+        } else if let Some((path, line, column)) = open {
+            self.open_at(path, line, column);
+        } else if review {
+            self.review_replace_in_files();
+        } else if apply {
+            self.apply_replace_in_files();
+        } else if undo {
+            self.undo_replace_in_files(ctx);
+        }
+    }
+}
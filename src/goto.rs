@@ -0,0 +1,185 @@
+use eframe::egui;
+use crate::app::TextEditorApp;
+
+// Where Go To jumps to. Lines and columns are 1-based, as compilers print them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GotoTarget {
+    Line { line: usize, column: Option<usize> },
+    Relative(isize), // Lines down from the cursor, or up if negative
+    Offset(u64), // Byte offset into the file, see goto_position
+}
+
+// Accepts "LINE", "LINE:COL", ":+N" or ":-N", and "#OFFSET" (decimal or 0x hex).
+// A trailing colon is ignored, so "12:5:" pasted from a compiler error works.
+pub fn parse_goto(text: &str) -> Option<GotoTarget> {
+    let text = text.trim();
+    if let Some(offset) = text.strip_prefix('#') {
+        let offset = offset.trim();
+        let parsed = match offset.strip_prefix("0x").or_else(|| offset.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => offset.parse(),
+        };
+        return parsed.ok().map(GotoTarget::Offset);
+    }
+    if let Some(relative) = text.strip_prefix(':') {
+        // The sign is required, a bare ":N" would read like a column
+        if !relative.starts_with(['+', '-']) {
+            return None;
+        }
+        return relative.trim().parse().ok().map(GotoTarget::Relative);
+    }
+    let text = text.strip_suffix(':').unwrap_or(text);
+    let (line, column) = match text.split_once(':') {
+        Some((line, column)) => (line.trim(), Some(column.trim().parse().ok()?)),
+        None => (text, None),
+    };
+    Some(GotoTarget::Line { line: line.parse().ok()?, column })
+}
+
+pub struct GotoDialog {
+    pub input: String,
+    pub focus: bool, // Put the keyboard in the input next frame
+}
+
+impl TextEditorApp {
+    pub fn open_goto_dialog(&mut self) {
+        self.goto_dialog = Some(GotoDialog { input: String::new(), focus: true });
+    }
+
+    // 0-based line of the cursor, or the top line shown in the large file viewer
+    fn current_line(&self, ctx: &egui::Context) -> usize {
+        if let Some(large_file) = &self.large_file {
+            return large_file.top_line;
+        }
+        let cursor = egui::TextEdit::load_state(ctx, self.editor_id())
+            .and_then(|state| state.cursor.char_range())
+            .map_or(0, |range| range.primary.index);
+        let rope = self.document.rope();
+        rope.char_to_line(cursor.min(rope.len_chars()))
+    }
+
+    // Moves the cursor there and scrolls it into view. The rope keeps an index
+    // of line starts, so this takes the same time anywhere in a big file.
+    // Offsets count bytes of the file as it is on disk in every viewer, the
+    // way a hex dump or a tool's error message would: for text that means in
+    // its encoding and after its BOM. Compressed files count decompressed bytes.
+    pub fn goto_position(&mut self, ctx: &egui::Context, target: GotoTarget) {
+        // The hex editor has no lines
+        if self.hex_editor.is_some() && !matches!(target, GotoTarget::Offset(_)) {
+            return;
+        }
+        match target {
+            GotoTarget::Line { line, column } => self.goto_line(ctx, line, column),
+            GotoTarget::Relative(delta) => {
+                let line = self.current_line(ctx).saturating_add_signed(delta);
+                self.goto_line(ctx, line + 1, None);
+            }
+            GotoTarget::Offset(offset) => {
+                if let Some(hex_editor) = &mut self.hex_editor {
+                    hex_editor.move_cursor(usize::try_from(offset).unwrap_or(usize::MAX));
+                } else if let Some(large_file) = &mut self.large_file {
+                    if let Ok(line) = large_file.line_of_offset(offset.min(large_file.file_size)) {
+                        large_file.goto_line(line);
+                    }
+                } else {
+                    let char_index = self.document.encoding().char_at_offset(self.document.rope(), offset);
+                    self.view.select = Some((char_index, char_index));
+                    ctx.memory_mut(|m| m.request_focus(self.editor_id()));
+                }
+            }
+        }
+    }
+
+    pub fn show_goto_dialog(&mut self, ctx: &egui::Context) {
+        if self.goto_dialog.is_none() {
+            return;
+        }
+        let (line, lines) = if let Some(large_file) = &self.large_file {
+            (large_file.top_line + 1, large_file.line_count().0)
+        } else {
+            (self.current_line(ctx) + 1, self.document.rope().len_lines())
+        };
+        let binary = self.hex_editor.is_some();
+        let Some(dialog) = &mut self.goto_dialog else {
+            return;
+        };
+        let target = parse_goto(&dialog.input);
+        let mut go = false;
+        let mut cancel = false;
+
+        egui::Window::new("Go To")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_TOP, [0.0, 40.0])
+            .show(ctx, |ui| {
+                let response = ui.add(egui::TextEdit::singleline(&mut dialog.input)
+                    .desired_width(260.0)
+                    .hint_text("line, line:col, :+N, :-N or #offset"));
+                if std::mem::take(&mut dialog.focus) {
+                    response.request_focus();
+                }
+                if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    go = true;
+                }
+                if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                    cancel = true;
+                }
+                if binary {
+                    ui.label("Binary files only have byte offsets, e.g. #0x1F0");
+                } else {
+                    ui.label(format!("Line {} of {}", line, lines));
+                }
+                if target.is_none() && !dialog.input.trim().is_empty() {
+                    ui.colored_label(ui.visuals().error_fg_color, "Not a position");
+                }
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(target.is_some(), egui::Button::new("Go")).clicked() {
+                        go = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        cancel = true;
+                    }
+                });
+            });
+
+        if let (true, Some(target)) = (go, target) {
+            self.goto_dialog = None;
+            self.goto_position(ctx, target);
+        } else if cancel {
+            self.goto_dialog = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_and_columns() {
+        assert_eq!(parse_goto("12"), Some(GotoTarget::Line { line: 12, column: None }));
+        assert_eq!(parse_goto(" 12:5 "), Some(GotoTarget::Line { line: 12, column: Some(5) }));
+        // As compilers print them
+        assert_eq!(parse_goto("12:5:"), Some(GotoTarget::Line { line: 12, column: Some(5) }));
+        assert_eq!(parse_goto("12:"), Some(GotoTarget::Line { line: 12, column: None }));
+        assert_eq!(parse_goto("12:x"), None);
+        assert_eq!(parse_goto(""), None);
+    }
+
+    #[test]
+    fn relative_moves() {
+        assert_eq!(parse_goto(":+3"), Some(GotoTarget::Relative(3)));
+        assert_eq!(parse_goto(":-10"), Some(GotoTarget::Relative(-10)));
+        assert_eq!(parse_goto(":5"), None);
+        assert_eq!(parse_goto(":"), None);
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(parse_goto("#100"), Some(GotoTarget::Offset(100)));
+        assert_eq!(parse_goto("#0x1F0"), Some(GotoTarget::Offset(0x1f0)));
+        assert_eq!(parse_goto("# 0XFF"), Some(GotoTarget::Offset(255)));
+        assert_eq!(parse_goto("#0xZZ"), None);
+        assert_eq!(parse_goto("#-1"), None);
+    }
+}
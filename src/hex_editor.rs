@@ -0,0 +1,289 @@
+use eframe::egui;
+
+const BYTES_PER_ROW: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Pane {
+    Hex,
+    Ascii,
+}
+
+// Byte-level editor for files that aren't text. The bytes are kept exactly
+// as read, so saving writes back an identical file apart from the edits.
+pub struct HexEditor {
+    pub bytes: Vec<u8>,
+    pub cursor: usize,
+    pub insert_mode: bool, // Typing inserts new bytes instead of overwriting
+    pub modified: bool,
+    pub read_only: bool, // Browsing and searching still work, editing doesn't
+    pub goto_input: String,
+    pub search_input: String,
+    pub search_as_hex: bool, // Treat the search text as hex bytes ("DE AD BE EF")
+    pub status: Option<String>,
+    low_nibble: bool, // The next hex digit goes into the low half of the byte
+    pane: Pane,
+    scroll_to_cursor: bool,
+}
+
+impl HexEditor {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            cursor: 0,
+            insert_mode: false,
+            modified: false,
+            read_only: false,
+            goto_input: String::new(),
+            search_input: String::new(),
+            search_as_hex: true,
+            status: None,
+            low_nibble: false,
+            pane: Pane::Hex,
+            scroll_to_cursor: false,
+        }
+    }
+
+    pub fn move_cursor(&mut self, to: usize) {
+        self.cursor = to.min(self.bytes.len());
+        self.low_nibble = false;
+        self.scroll_to_cursor = true;
+    }
+
+    pub fn goto_offset(&mut self, input: &str) {
+        let input = input.trim();
+        let parsed = match input.strip_prefix("0x").or_else(|| input.strip_prefix("0X")) {
+            Some(hex) => usize::from_str_radix(hex, 16),
+            None => input.parse::<usize>(),
+        };
+        match parsed {
+            Ok(offset) if offset <= self.bytes.len() => {
+                self.move_cursor(offset);
+                self.status = None;
+            }
+            Ok(_) => self.status = Some("Offset is past the end of the file".to_string()),
+            Err(_) => self.status = Some("Enter a decimal offset or 0x followed by hex".to_string()),
+        }
+    }
+
+    // Finds the next occurrence after the cursor, wrapping around to the start
+    pub fn find_next(&mut self) {
+        let pattern = if self.search_as_hex {
+            match parse_hex_pattern(&self.search_input) {
+                Some(pattern) => pattern,
+                None => {
+                    self.status = Some("Hex pattern must be pairs of hex digits".to_string());
+                    return;
+                }
+            }
+        } else {
+            self.search_input.as_bytes().to_vec()
+        };
+        if pattern.is_empty() {
+            return;
+        }
+
+        let start = (self.cursor + 1).min(self.bytes.len());
+        let found = memchr::memmem::find(&self.bytes[start..], &pattern)
+            .map(|i| start + i)
+            .or_else(|| memchr::memmem::find(&self.bytes[..start.min(self.bytes.len())], &pattern));
+        match found {
+            Some(offset) => {
+                self.move_cursor(offset);
+                self.status = None;
+            }
+            None => self.status = Some("No matches".to_string()),
+        }
+    }
+
+    fn type_hex_digit(&mut self, digit: u8) {
+        if !self.low_nibble {
+            if self.insert_mode || self.cursor == self.bytes.len() {
+                self.bytes.insert(self.cursor, digit << 4);
+            } else {
+                self.bytes[self.cursor] = (self.bytes[self.cursor] & 0x0F) | (digit << 4);
+            }
+            self.low_nibble = true;
+        } else {
+            self.bytes[self.cursor] = (self.bytes[self.cursor] & 0xF0) | digit;
+            self.low_nibble = false;
+            self.cursor += 1;
+        }
+        self.modified = true;
+        self.scroll_to_cursor = true;
+    }
+
+    fn type_byte(&mut self, byte: u8) {
+        if self.insert_mode || self.cursor == self.bytes.len() {
+            self.bytes.insert(self.cursor, byte);
+        } else {
+            self.bytes[self.cursor] = byte;
+        }
+        self.cursor += 1;
+        self.low_nibble = false;
+        self.modified = true;
+        self.scroll_to_cursor = true;
+    }
+
+    fn delete_at(&mut self, offset: usize) {
+        if offset < self.bytes.len() {
+            self.bytes.remove(offset);
+            self.modified = true;
+        }
+        self.move_cursor(offset);
+    }
+
+    fn handle_keys(&mut self, ui: &egui::Ui) {
+        // Leave the keyboard alone while the go-to or search fields are being typed in
+        if ui.ctx().memory(|m| m.focused().is_some()) {
+            return;
+        }
+        let events = ui.input(|i| i.events.clone());
+        for event in events {
+            match event {
+                egui::Event::Key { key, pressed: true, modifiers, .. } if !modifiers.command => match key {
+                    egui::Key::ArrowLeft => self.move_cursor(self.cursor.saturating_sub(1)),
+                    egui::Key::ArrowRight => self.move_cursor(self.cursor + 1),
+                    egui::Key::ArrowUp => self.move_cursor(self.cursor.saturating_sub(BYTES_PER_ROW)),
+                    egui::Key::ArrowDown => self.move_cursor(self.cursor + BYTES_PER_ROW),
+                    egui::Key::PageUp => self.move_cursor(self.cursor.saturating_sub(BYTES_PER_ROW * 16)),
+                    egui::Key::PageDown => self.move_cursor(self.cursor + BYTES_PER_ROW * 16),
+                    egui::Key::Home => self.move_cursor(self.cursor - self.cursor % BYTES_PER_ROW),
+                    egui::Key::End => self.move_cursor(self.cursor - self.cursor % BYTES_PER_ROW + BYTES_PER_ROW - 1),
+                    egui::Key::Insert => self.insert_mode = !self.insert_mode,
+                    egui::Key::Tab => {
+                        self.pane = if self.pane == Pane::Hex { Pane::Ascii } else { Pane::Hex };
+                        self.low_nibble = false;
+                    }
+                    egui::Key::Delete if !self.read_only => self.delete_at(self.cursor),
+                    egui::Key::Backspace if self.cursor > 0 && !self.read_only => self.delete_at(self.cursor - 1),
+                    _ => {}
+                },
+                egui::Event::Text(text) if !self.read_only => {
+                    for c in text.chars() {
+                        match self.pane {
+                            Pane::Hex => {
+                                if let Some(digit) = c.to_digit(16) {
+                                    self.type_hex_digit(digit as u8);
+                                }
+                            }
+                            Pane::Ascii => {
+                                if c.is_ascii() && !c.is_ascii_control() {
+                                    self.type_byte(c as u8);
+                                }
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Go to offset:");
+            let goto = ui.add(egui::TextEdit::singleline(&mut self.goto_input).desired_width(100.0));
+            if (goto.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))) || ui.button("Go").clicked() {
+                let input = self.goto_input.clone();
+                self.goto_offset(&input);
+            }
+            ui.separator();
+            ui.label("Find:");
+            let find = ui.add(egui::TextEdit::singleline(&mut self.search_input).desired_width(160.0));
+            ui.checkbox(&mut self.search_as_hex, "Hex");
+            if (find.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))) || ui.button("Find Next").clicked() {
+                self.find_next();
+            }
+            ui.separator();
+            let mode = if self.insert_mode { "INS" } else { "OVR" };
+            if ui.button(mode).on_hover_text("Toggle insert/overwrite (Insert key)").clicked() {
+                self.insert_mode = !self.insert_mode;
+            }
+            ui.label(format!("Offset 0x{:08X} of {} bytes", self.cursor, self.bytes.len()));
+            if let Some(status) = &self.status {
+                ui.label(status);
+            }
+        });
+        ui.separator();
+
+        self.handle_keys(ui);
+
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace) + ui.spacing().item_spacing.y;
+        let rows = self.bytes.len() / BYTES_PER_ROW + 1;
+        let mut scroll = egui::ScrollArea::vertical().auto_shrink([false, false]);
+        if std::mem::take(&mut self.scroll_to_cursor) {
+            // Only scroll when the cursor row is off screen
+            let cursor_top = (self.cursor / BYTES_PER_ROW) as f32 * row_height;
+            let offset = ui.ctx().data(|d| d.get_temp::<f32>(egui::Id::new("hex_scroll"))).unwrap_or(0.0);
+            let visible = ui.available_height();
+            if cursor_top < offset || cursor_top + row_height > offset + visible {
+                scroll = scroll.vertical_scroll_offset((cursor_top - visible / 2.0).max(0.0));
+            }
+        }
+
+        let output = scroll.show_rows(ui, row_height, rows, |ui, row_range| {
+            for row in row_range {
+                ui.horizontal(|ui| {
+                    ui.spacing_mut().item_spacing.x = 0.0;
+                    let start = row * BYTES_PER_ROW;
+                    ui.label(egui::RichText::new(format!("{:08X}  ", start)).monospace().weak());
+
+                    for i in start..start + BYTES_PER_ROW {
+                        let text = match self.bytes.get(i) {
+                            Some(b) => format!("{:02X} ", b),
+                            None => "   ".to_string(),
+                        };
+                        if self.cell(ui, text, i, Pane::Hex) {
+                            self.pane = Pane::Hex;
+                            self.move_cursor(i);
+                        }
+                    }
+
+                    ui.label(egui::RichText::new(" ").monospace());
+                    for i in start..start + BYTES_PER_ROW {
+                        let text = match self.bytes.get(i) {
+                            Some(b) if b.is_ascii_graphic() || *b == b' ' => (*b as char).to_string(),
+                            Some(_) => ".".to_string(),
+                            None => " ".to_string(),
+                        };
+                        if self.cell(ui, text, i, Pane::Ascii) {
+                            self.pane = Pane::Ascii;
+                            self.move_cursor(i);
+                        }
+                    }
+                });
+            }
+        });
+        ui.ctx().data_mut(|d| d.insert_temp(egui::Id::new("hex_scroll"), output.state.offset.y));
+    }
+
+    // Draws one byte in one of the panes; returns true if it was clicked
+    fn cell(&self, ui: &mut egui::Ui, text: String, offset: usize, pane: Pane) -> bool {
+        let mut rich = egui::RichText::new(text).monospace();
+        if offset == self.cursor && offset <= self.bytes.len() {
+            rich = if pane == self.pane {
+                rich.background_color(ui.visuals().selection.bg_fill)
+            } else {
+                rich.background_color(ui.visuals().faint_bg_color).underline()
+            };
+        }
+        if offset > self.bytes.len() {
+            ui.label(rich);
+            return false;
+        }
+        ui.add(egui::Label::new(rich).sense(egui::Sense::click())).clicked()
+    }
+}
+
+// Parses "DE AD be ef" or "deadbeef" into bytes
+pub fn parse_hex_pattern(input: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = input
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<_>>()?;
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    Some(digits.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect())
+}
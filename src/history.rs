@@ -0,0 +1,312 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use eframe::egui;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use similar::ChangeTag;
+use crate::app::TextEditorApp;
+use crate::atomic_write::write_atomic;
+use crate::compression::decompress_if_compressed;
+use crate::diff_view::{diff_lines, show_diff};
+
+// Files bigger than this aren't copied into the history on every save
+const MAX_SNAPSHOT_SIZE: u64 = 16 * 1024 * 1024;
+
+// How much history is kept per file. Whichever limit is reached first wins.
+#[derive(Clone, Copy)]
+pub struct Retention {
+    pub max_versions: usize,
+    pub max_age: Duration,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            max_versions: 50,
+            max_age: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+}
+
+// FNV-1a, so the directory for a file stays the same across builds
+fn path_hash(path: &str) -> u64 {
+    path.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+// Versions of one file live in a directory named after its absolute path:
+//   history/<hash>/path                  the file's path, for people looking around
+//   history/<hash>/<unix millis>.gz      its contents before a save
+fn history_dir(path: &str) -> Option<PathBuf> {
+    let absolute = fs::canonicalize(path).map_or_else(|_| path.to_string(), |p| p.display().to_string());
+    Some(dirs::data_dir()?.join("amend").join("history").join(format!("{:016x}", path_hash(&absolute))))
+}
+
+pub struct Version {
+    pub path: PathBuf,
+    pub saved: SystemTime, // When the contents were replaced by a save
+    pub size: u64, // Compressed size
+}
+
+impl Version {
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        GzDecoder::new(File::open(&self.path)?).read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+// Newest first
+pub fn list_versions(path: &str) -> Vec<Version> {
+    let Some(entries) = history_dir(path).and_then(|dir| fs::read_dir(dir).ok()) else {
+        return Vec::new();
+    };
+    let mut versions: Vec<Version> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let millis: u64 = path.file_name()?.to_str()?.strip_suffix(".gz")?.parse().ok()?;
+            Some(Version {
+                saved: UNIX_EPOCH + Duration::from_millis(millis),
+                size: entry.metadata().ok()?.len(),
+                path,
+            })
+        })
+        .collect();
+    versions.sort_by_key(|version| std::cmp::Reverse(version.saved));
+    versions
+}
+
+// Drops versions beyond the retention limits
+fn prune(path: &str, retention: Retention) {
+    for (i, version) in list_versions(path).iter().enumerate() {
+        let too_old = version.saved.elapsed().is_ok_and(|age| age > retention.max_age);
+        if i >= retention.max_versions || too_old {
+            let _ = fs::remove_file(&version.path);
+        }
+    }
+}
+
+// Keeps a compressed copy of the file as it is on disk now. The file is read
+// right away, before the save replaces it; compressing happens on a
+// background thread. Returns false if the file is too big to keep.
+pub fn record(path: &str, retention: Retention) -> bool {
+    if retention.max_versions == 0 {
+        return true;
+    }
+    let Some(dir) = history_dir(path) else {
+        return true;
+    };
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.len() > MAX_SNAPSHOT_SIZE => return false,
+        Ok(_) => {}
+        Err(_) => return true,
+    }
+    // Nothing worth keeping
+    let Ok(bytes) = fs::read(path) else {
+        return true;
+    };
+    if bytes.is_empty() {
+        return true;
+    }
+    let path = path.to_string();
+    thread::spawn(move || {
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis());
+        let _ = fs::create_dir_all(&dir);
+        let _ = fs::write(dir.join("path"), &path);
+        let _ = write_atomic(&dir.join(format!("{}.gz", millis)), |out| {
+            let mut encoder = GzEncoder::new(out, Compression::default());
+            encoder.write_all(&bytes)?;
+            encoder.finish()?;
+            Ok(())
+        });
+        prune(&path, retention);
+    });
+    true
+}
+
+pub struct HistoryPanel {
+    pub filename: String, // The file the versions belong to
+    pub versions: Vec<Version>,
+    pub diff: Option<(usize, Vec<(ChangeTag, String)>)>, // Index of the version being compared and its diff
+    pub confirm_restore: Option<usize>, // Version waiting for a yes, since the hex editor can't undo a restore
+}
+
+impl HistoryPanel {
+    pub fn new(filename: String) -> Self {
+        Self {
+            versions: list_versions(&filename),
+            filename,
+            diff: None,
+            confirm_restore: None,
+        }
+    }
+}
+
+// "2024-05-01 13:45:10 UTC", worked out by hand since nothing else needs a date library
+fn format_time(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rest) = (secs / 86400, secs % 86400);
+    // Days since 1970-01-01 to a civil date, after Howard Hinnant's civil_from_days
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, rest / 3600, rest / 60 % 60, rest % 60)
+}
+
+fn format_age(time: SystemTime) -> String {
+    let secs = time.elapsed().map_or(0, |d| d.as_secs());
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{} min ago", secs / 60),
+        3600..86400 => format!("{} h ago", secs / 3600),
+        _ => format!("{} days ago", secs / 86400),
+    }
+}
+
+impl TextEditorApp {
+    // Called before saving over the open file
+    pub fn record_history(&mut self) {
+        if let Some(filename) = self.filename.clone() {
+            self.record_history_of(&filename);
+        }
+    }
+
+    // Called before anything writes over the file. Saying once per file that
+    // it's too big is enough.
+    pub fn record_history_of(&mut self, path: &str) {
+        if !record(path, self.history_retention) && self.too_big_for_history.insert(path.to_string()) {
+            self.notifications.info(format!("{} is over {} MB, so its earlier versions aren't kept in the local history",
+                path, MAX_SNAPSHOT_SIZE / (1024 * 1024)));
+        }
+    }
+
+    // A version's bytes as text, decoded the same way as the document
+    fn decode_version(&self, bytes: Vec<u8>) -> String {
+        // The history keeps the file as it was on disk, compressed or not
+        let bytes = decompress_if_compressed(bytes);
+        let encoding = self.document.encoding();
+        let bytes = bytes.strip_prefix(encoding.bom_bytes()).unwrap_or(&bytes);
+        encoding.encoding.decode_without_bom_handling(bytes).0.into_owned()
+    }
+
+    pub fn toggle_history_panel(&mut self) {
+        self.history_panel = match (&self.history_panel, &self.filename) {
+            (None, Some(filename)) => Some(HistoryPanel::new(filename.clone())),
+            _ => None,
+        };
+    }
+
+    pub fn show_history_panel(&mut self, ctx: &egui::Context) {
+        let Some(panel) = &mut self.history_panel else {
+            return;
+        };
+        // Follow the tab in front
+        match &self.filename {
+            Some(filename) if *filename != panel.filename => *panel = HistoryPanel::new(filename.clone()),
+            Some(_) => {}
+            None => {
+                self.history_panel = None;
+                return;
+            }
+        }
+
+        let mut compare = None;
+        let mut restore = None;
+        let mut refresh = false;
+        let mut close = false;
+        egui::SidePanel::right("history").default_width(360.0).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.heading("Local History");
+                refresh = ui.small_button("Refresh").clicked();
+                close = ui.small_button("Close").clicked();
+            });
+            ui.label("Versions of this file from before each save.");
+            ui.separator();
+            if panel.versions.is_empty() {
+                ui.label("No versions yet. One is kept every time the file is saved.");
+            }
+            egui::ScrollArea::vertical().id_salt("history_versions").max_height(200.0).show(ui, |ui| {
+                for (i, version) in panel.versions.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{} ({})", format_time(version.saved), format_age(version.saved)))
+                            .on_hover_text(format!("{} bytes compressed", version.size));
+                        if ui.add_enabled(self.hex_editor.is_none(), egui::Button::new("Compare"))
+                            .on_hover_text("Compare with the text in the editor")
+                            .clicked()
+                        {
+                            compare = Some(i);
+                        }
+                        // A partial document would splice the whole version into the rest of the file
+                        let can_restore = self.document.partial().is_none() && !self.read_only;
+                        if panel.confirm_restore == Some(i) {
+                            ui.colored_label(ui.visuals().warn_fg_color, "Replace the bytes? This can't be undone.");
+                            if ui.button("Restore").clicked() {
+                                restore = Some(i);
+                            }
+                            if ui.button("Cancel").clicked() {
+                                panel.confirm_restore = None;
+                            }
+                        } else if ui.add_enabled(can_restore, egui::Button::new("Restore")).clicked() {
+                            if self.hex_editor.is_some() {
+                                panel.confirm_restore = Some(i);
+                            } else {
+                                restore = Some(i);
+                            }
+                        }
+                    });
+                }
+            });
+            if let Some((i, lines)) = &panel.diff {
+                ui.separator();
+                ui.label(format!("Editor compared to the version from {}:", format_age(panel.versions[*i].saved)));
+                show_diff(ui, lines);
+            }
+        });
+
+        if let Some(i) = compare {
+            match panel.versions[i].read() {
+                Ok(bytes) => {
+                    let diff = diff_lines(&self.decode_version(bytes), &self.document.to_string());
+                    if let Some(panel) = &mut self.history_panel {
+                        panel.diff = Some((i, diff));
+                    }
+                }
+                Err(error) => self.notifications.error(format!("Couldn't read that version: {}", error), Vec::new()),
+            }
+        } else if let Some(i) = restore {
+            panel.confirm_restore = None;
+            match panel.versions[i].read() {
+                Ok(bytes) => {
+                    // Text goes into the editor as an edit, so it can be undone. The
+                    // hex editor has no undo, which is why it asked first. Either way
+                    // it still has to be saved.
+                    if let Some(hex_editor) = &mut self.hex_editor {
+                        // The history keeps the file as it was on disk, compressed or not
+                        hex_editor.bytes = decompress_if_compressed(bytes);
+                        hex_editor.cursor = hex_editor.cursor.min(hex_editor.bytes.len());
+                        hex_editor.modified = true;
+                    } else {
+                        let text = self.decode_version(bytes);
+                        self.document.replace_all(&text);
+                    }
+                    self.notifications.info("Version restored; save to keep it");
+                }
+                Err(error) => self.notifications.error(format!("Couldn't read that version: {}", error), Vec::new()),
+            }
+        } else if refresh {
+            *panel = HistoryPanel::new(panel.filename.clone());
+        } else if close {
+            self.history_panel = None;
+        }
+    }
+}
@@ -0,0 +1,390 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use eframe::egui;
+use encoding_rs::{UTF_16BE, UTF_16LE};
+use crate::encoding::{detect_encoding, TextEncoding, SNIFF_LEN};
+
+// Files above this size are paged from disk instead of being loaded into a Document
+pub const LARGE_FILE_THRESHOLD: u64 = 100 * 1024 * 1024;
+
+const LINE_STRIDE: usize = 256; // The index remembers where every 256th line starts
+const MAX_LINE_DISPLAY: usize = 4096; // Longer lines are cut off on screen
+const READ_BLOCK: usize = 1024 * 1024;
+
+// How long a status message stays up
+const STATUS_TIME: Duration = Duration::from_secs(5);
+
+// Sparse line index: checkpoints[i] is the byte offset where line i * LINE_STRIDE starts
+struct LineIndex {
+    checkpoints: Vec<u64>,
+    newlines: usize,
+    indexed_bytes: u64,
+    done: bool,
+    error: Option<String>, // Why indexing stopped short of the end
+}
+
+pub struct VisibleLine {
+    pub number: usize,
+    pub offset: u64,
+    pub text: String,
+    pub truncated: bool,
+}
+
+// Read-only view of a file too big to edit. Line offsets are indexed on a
+// background thread and only the lines on screen are ever read.
+pub struct LargeFile {
+    pub path: String,
+    pub file_size: u64,
+    pub encoding: TextEncoding, // Lines are split on the byte 0x0A, so this is never UTF-16
+    file: File,
+    index: Arc<Mutex<LineIndex>>,
+    cancel: Arc<AtomicBool>, // Stops the indexing thread when the view closes
+    pub top_line: usize,
+    scroll_remainder: f32,
+    pub goto_input: String,
+    pub highlight: Option<(u64, usize)>, // Byte offset and length of the current match
+    pub status: Option<(String, Instant)>, // Shown for STATUS_TIME from when it was set
+    window: Option<(usize, usize, Vec<VisibleLine>)>, // Cached lines for (top_line, rows)
+}
+
+impl Drop for LargeFile {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+impl LargeFile {
+    // Fails with ErrorKind::Unsupported for UTF-16, whose line breaks aren't
+    // single bytes
+    pub fn open(path: String) -> io::Result<Self> {
+        let mut file = File::open(&path)?;
+        let file_size = file.metadata()?.len();
+        let mut sample = Vec::with_capacity(SNIFF_LEN);
+        file.by_ref().take(SNIFF_LEN as u64).read_to_end(&mut sample)?;
+        let (encoding, _) = detect_encoding(&sample, file_size <= SNIFF_LEN as u64);
+        if encoding.encoding == UTF_16LE || encoding.encoding == UTF_16BE {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "the large file viewer can't show UTF-16"));
+        }
+        let index = Arc::new(Mutex::new(LineIndex {
+            checkpoints: vec![0],
+            newlines: 0,
+            indexed_bytes: 0,
+            done: false,
+            error: None,
+        }));
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        // Separate handles, since cloned ones would share the read position
+        let index_file = File::open(&path)?;
+        let index_clone = Arc::clone(&index);
+        let cancel_clone = Arc::clone(&cancel);
+        thread::spawn(move || build_index(index_file, index_clone, cancel_clone));
+
+        Ok(Self {
+            path,
+            file_size,
+            encoding,
+            file,
+            index,
+            cancel,
+            top_line: 0,
+            scroll_remainder: 0.0,
+            goto_input: String::new(),
+            highlight: None,
+            status: None,
+            window: None,
+        })
+    }
+
+    // Number of lines known so far, and whether indexing has finished
+    pub fn line_count(&self) -> (usize, bool) {
+        match self.index.lock() {
+            Ok(index) => (index.newlines + 1, index.done),
+            Err(_) => (1, true),
+        }
+    }
+
+    pub fn indexing_progress(&self) -> f32 {
+        match self.index.lock() {
+            Ok(index) if self.file_size > 0 => index.indexed_bytes as f32 / self.file_size as f32,
+            _ => 1.0,
+        }
+    }
+
+    // Line number containing the given byte offset
+    pub fn line_of_offset(&mut self, offset: u64) -> io::Result<usize> {
+        let (checkpoint, start) = match self.index.lock() {
+            Ok(index) => {
+                let i = index.checkpoints.partition_point(|c| *c <= offset).saturating_sub(1);
+                (i, index.checkpoints[i])
+            }
+            Err(_) => (0, 0),
+        };
+        let mut reader = BufReader::with_capacity(64 * 1024, &self.file);
+        reader.seek(SeekFrom::Start(start))?;
+        let mut line = checkpoint * LINE_STRIDE;
+        let mut remaining = offset - start;
+        while remaining > 0 {
+            let buf = reader.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+            let take = buf.len().min(remaining as usize);
+            line += memchr::memchr_iter(b'\n', &buf[..take]).count();
+            reader.consume(take);
+            remaining -= take as u64;
+        }
+        Ok(line)
+    }
+
+    pub fn read_lines(&mut self, first_line: usize, count: usize) -> io::Result<Vec<VisibleLine>> {
+        let (mut line, start) = match self.index.lock() {
+            Ok(index) => {
+                let i = (first_line / LINE_STRIDE).min(index.checkpoints.len() - 1);
+                (i * LINE_STRIDE, index.checkpoints[i])
+            }
+            Err(_) => (0, 0),
+        };
+        let mut reader = BufReader::with_capacity(64 * 1024, &self.file);
+        reader.seek(SeekFrom::Start(start))?;
+
+        let mut lines = Vec::with_capacity(count);
+        let mut current = Vec::new();
+        let mut truncated = false;
+        let mut offset = start;
+        let mut line_start = start;
+        while lines.len() < count {
+            let buf = reader.fill_buf()?;
+            if buf.is_empty() {
+                if line >= first_line && line_start < self.file_size.max(1) {
+                    lines.push(self.make_line(line, line_start, &current, truncated));
+                }
+                break;
+            }
+            let (end, newline) = match memchr::memchr(b'\n', buf) {
+                Some(i) => (i, true),
+                None => (buf.len(), false),
+            };
+            if line >= first_line {
+                let room = MAX_LINE_DISPLAY.saturating_sub(current.len());
+                current.extend_from_slice(&buf[..end.min(room)]);
+                truncated |= end > room;
+            }
+            let consumed = if newline { end + 1 } else { end };
+            reader.consume(consumed);
+            offset += consumed as u64;
+            if newline {
+                if line >= first_line {
+                    lines.push(self.make_line(line, line_start, &current, truncated));
+                }
+                current.clear();
+                truncated = false;
+                line += 1;
+                line_start = offset;
+            }
+        }
+        Ok(lines)
+    }
+
+    fn make_line(&self, number: usize, offset: u64, bytes: &[u8], truncated: bool) -> VisibleLine {
+        let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+        // The BOM isn't part of the first line's text
+        let bom = if offset == 0 { self.encoding.bom_bytes() } else { &[] };
+        let bytes = bytes.strip_prefix(bom).unwrap_or(bytes);
+        VisibleLine {
+            number,
+            offset: offset + bom.len() as u64,
+            text: self.encoding.encoding.decode_without_bom_handling(bytes).0.into_owned(),
+            truncated,
+        }
+    }
+
+    fn set_status(&mut self, status: String) {
+        self.status = Some((status, Instant::now()));
+    }
+
+    pub fn goto_line(&mut self, line: usize) {
+        let (lines, _) = self.line_count();
+        self.top_line = line.min(lines.saturating_sub(1));
+    }
+
+    // Draws the viewer into the remaining space, with the given matches marked.
+    // Returns true if the user asked to load the start of the file into the
+    // editor instead.
+    pub fn show(&mut self, ui: &mut egui::Ui, marks: &[(u64, usize)]) -> bool {
+        let (lines, indexed) = self.line_count();
+        let mut edit_preview = false;
+
+        ui.horizontal(|ui| {
+            ui.label("Go to line:");
+            let goto = ui.add(egui::TextEdit::singleline(&mut self.goto_input).desired_width(80.0));
+            if (goto.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))) || ui.button("Go").clicked() {
+                if let Ok(line) = self.goto_input.trim().parse::<usize>() {
+                    self.goto_line(line.saturating_sub(1));
+                }
+            }
+            ui.separator();
+            let index_error = self.index.lock().ok().and_then(|index| index.error.clone());
+            if let Some(error) = index_error {
+                ui.colored_label(ui.visuals().error_fg_color, format!("Only the first {} lines could be read: {}", lines, error));
+            } else if indexed {
+                ui.label(format!("{} lines, {} bytes", lines, self.file_size));
+            } else {
+                ui.label(format!("Indexing lines… {:.0}%", self.indexing_progress() * 100.0));
+                ui.ctx().request_repaint();
+            }
+            let shown_for = self.status.as_ref().map(|(_, since)| since.elapsed());
+            if shown_for.is_some_and(|shown_for| shown_for >= STATUS_TIME) {
+                self.status = None;
+            }
+            if let (Some((status, _)), Some(shown_for)) = (&self.status, shown_for) {
+                ui.label(status);
+                ui.ctx().request_repaint_after(STATUS_TIME - shown_for);
+            }
+            ui.separator();
+            if ui.button("Edit first 10 MB").clicked() {
+                edit_preview = true;
+            }
+        });
+        ui.separator();
+
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        let rows = ((ui.available_height() / row_height) as usize).max(1);
+        let max_top = lines.saturating_sub(rows);
+
+        // Scrolling by wheel and keys, in whole lines
+        if ui.ui_contains_pointer() {
+            let (wheel, page_up, page_down) = ui.input(|i| {
+                (i.smooth_scroll_delta.y, i.key_pressed(egui::Key::PageUp), i.key_pressed(egui::Key::PageDown))
+            });
+            self.scroll_remainder -= wheel / row_height;
+            let whole = self.scroll_remainder.trunc();
+            self.scroll_remainder -= whole;
+            let mut top = self.top_line as i64 + whole as i64;
+            if page_up {
+                top -= rows as i64;
+            }
+            if page_down {
+                top += rows as i64;
+            }
+            self.top_line = top.clamp(0, max_top as i64) as usize;
+        }
+        self.top_line = self.top_line.min(max_top);
+
+        if self.window.as_ref().map(|(top, count, _)| (*top, *count)) != Some((self.top_line, rows)) || !indexed {
+            match self.read_lines(self.top_line, rows) {
+                Ok(visible) => self.window = Some((self.top_line, rows, visible)),
+                Err(e) => self.set_status(format!("Read error: {}", e)),
+            }
+        }
+
+        ui.horizontal_top(|ui| {
+            let gutter_width = format!("{}", lines).len().max(4);
+            let text_width = ui.available_width() - 24.0;
+            egui::ScrollArea::horizontal()
+                .max_width(text_width)
+                .auto_shrink([false, false])
+                .show(ui, |ui| {
+                    ui.spacing_mut().item_spacing.y = 0.0;
+                    if let Some((_, _, visible)) = &self.window {
+                        for line in visible {
+                            let job = line_layout(ui, line, gutter_width, marks, self.highlight);
+                            ui.label(job);
+                        }
+                    }
+                });
+
+            // Scrollbar over line numbers; f64 keeps it exact for billions of lines
+            let mut position = (max_top - self.top_line) as f64;
+            let slider = egui::Slider::new(&mut position, 0.0..=max_top.max(1) as f64)
+                .vertical()
+                .show_value(false);
+            ui.spacing_mut().slider_width = ui.available_height();
+            if ui.add(slider).changed() {
+                self.top_line = max_top.saturating_sub(position.round() as usize);
+            }
+        });
+
+        edit_preview
+    }
+}
+
+fn line_layout(ui: &egui::Ui, line: &VisibleLine, gutter_width: usize, marks: &[(u64, usize)], current: Option<(u64, usize)>) -> egui::text::LayoutJob {
+    let font = egui::TextStyle::Monospace.resolve(ui.style());
+    let plain = egui::TextFormat::simple(font.clone(), ui.visuals().text_color());
+    let gutter = egui::TextFormat::simple(font, ui.visuals().weak_text_color());
+    let mut marked = plain.clone();
+    marked.background = ui.visuals().warn_fg_color.gamma_multiply(0.35);
+    let mut selected = plain.clone();
+    selected.background = ui.visuals().selection.bg_fill;
+
+    let mut job = egui::text::LayoutJob::default();
+    job.append(&format!("{:>width$}  ", line.number + 1, width = gutter_width), 0.0, gutter.clone());
+
+    // Match offsets are in file bytes; only exact for lines whose decoded text is as long as their bytes
+    let line_end = line.offset + line.text.len() as u64;
+    let first = marks.partition_point(|(offset, len)| offset + *len as u64 <= line.offset);
+    let mut pos = 0;
+    for &(offset, len) in marks[first..].iter().chain(current.as_ref()) {
+        if offset < line.offset || offset >= line_end {
+            continue;
+        }
+        let start = (offset - line.offset) as usize;
+        let end = (start + len).min(line.text.len());
+        if start < pos || !line.text.is_char_boundary(start) || !line.text.is_char_boundary(end) {
+            continue;
+        }
+        let format = if Some((offset, len)) == current { selected.clone() } else { marked.clone() };
+        job.append(&line.text[pos..start], 0.0, plain.clone());
+        job.append(&line.text[start..end], 0.0, format);
+        pos = end;
+    }
+    job.append(&line.text[pos..], 0.0, plain);
+    if line.truncated {
+        job.append(" …", 0.0, gutter);
+    }
+    job
+}
+
+fn build_index(mut file: File, index: Arc<Mutex<LineIndex>>, cancel: Arc<AtomicBool>) {
+    let mut buffer = vec![0u8; READ_BLOCK];
+    let mut offset = 0u64;
+    let mut newlines = 0usize;
+    let mut new_checkpoints = Vec::new();
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        let n = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => {
+                // Shown in the viewer; the lines indexed so far still work
+                if let Ok(mut index) = index.lock() {
+                    index.error = Some(error.to_string());
+                }
+                break;
+            }
+        };
+        for i in memchr::memchr_iter(b'\n', &buffer[..n]) {
+            newlines += 1;
+            if newlines.is_multiple_of(LINE_STRIDE) {
+                new_checkpoints.push(offset + i as u64 + 1);
+            }
+        }
+        offset += n as u64;
+        if let Ok(mut index) = index.lock() {
+            index.checkpoints.append(&mut new_checkpoints);
+            index.newlines = newlines;
+            index.indexed_bytes = offset;
+        }
+    }
+    if let Ok(mut index) = index.lock() {
+        index.done = true;
+    }
+}
@@ -2,5 +2,29 @@ pub mod app;
 pub mod ui;
 pub mod file_ops;
 pub mod platform;
+pub mod document;
+pub mod undo;
+pub mod decoder;
+pub mod encoding;
+pub mod dialogs;
+pub mod line_ending;
+pub mod large_file;
+pub mod hex_editor;
+pub mod load_job;
+pub mod actions;
+pub mod atomic_write;
+pub mod notifications;
+pub mod recovery;
+pub mod diff_view;
+pub mod external_change;
+pub mod tabs;
+pub mod session;
+pub mod history;
+pub mod compression;
+pub mod read_only;
+pub mod find;
+pub mod find_in_files;
+pub mod replace_in_files;
+pub mod goto;
 
 pub use app::TextEditorApp;
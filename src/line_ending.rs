@@ -0,0 +1,125 @@
+use std::borrow::Cow;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl Default for LineEnding {
+    fn default() -> Self {
+        if cfg!(windows) {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        }
+    }
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+            LineEnding::Cr => "CR",
+        }
+    }
+
+    // Rewrites every line break in the text to this style
+    pub fn convert<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if !text.contains('\r') && (*self == LineEnding::Lf || !text.contains('\n')) {
+            return Cow::Borrowed(text);
+        }
+        let normalized = text.replace("\r\n", "\n").replace('\r', "\n");
+        match self {
+            LineEnding::Lf => Cow::Owned(normalized),
+            _ => Cow::Owned(normalized.replace('\n', self.as_str())),
+        }
+    }
+
+    // Rewrites bare LFs (what the text widget inserts for Enter) to this style
+    pub fn apply_to_typed<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if *self == LineEnding::Lf || !text.contains('\n') {
+            return Cow::Borrowed(text);
+        }
+        let mut out = String::with_capacity(text.len() + 8);
+        let mut previous = None;
+        for c in text.chars() {
+            if c == '\n' && previous != Some('\r') {
+                out.push_str(self.as_str());
+            } else {
+                out.push(c);
+            }
+            previous = Some(c);
+        }
+        Cow::Owned(out)
+    }
+}
+
+// Counts line breaks of each style in text that arrives in chunks. A CR at
+// the end of one chunk is held back in case the next chunk starts with LF.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LineEndingStats {
+    pub lf: usize,
+    pub crlf: usize,
+    pub cr: usize,
+    pending_cr: bool,
+}
+
+impl LineEndingStats {
+    pub fn feed(&mut self, text: &str) {
+        for b in text.bytes() {
+            match b {
+                b'\n' if self.pending_cr => {
+                    self.crlf += 1;
+                    self.pending_cr = false;
+                }
+                b'\n' => self.lf += 1,
+                b'\r' => {
+                    if self.pending_cr {
+                        self.cr += 1;
+                    }
+                    self.pending_cr = true;
+                }
+                _ if self.pending_cr => {
+                    self.cr += 1;
+                    self.pending_cr = false;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn finish(&mut self) {
+        if self.pending_cr {
+            self.cr += 1;
+            self.pending_cr = false;
+        }
+    }
+
+    // The most common style, None if the text has no line breaks at all
+    pub fn dominant(&self) -> Option<LineEnding> {
+        if self.lf == 0 && self.crlf == 0 && self.cr == 0 {
+            None
+        } else if self.crlf >= self.lf && self.crlf >= self.cr {
+            Some(LineEnding::CrLf)
+        } else if self.lf >= self.cr {
+            Some(LineEnding::Lf)
+        } else {
+            Some(LineEnding::Cr)
+        }
+    }
+
+    pub fn is_mixed(&self) -> bool {
+        [self.lf, self.crlf, self.cr].iter().filter(|n| **n > 0).count() > 1
+    }
+}
@@ -0,0 +1,150 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use ropey::Rope;
+use crate::file_ops::LoadedFile;
+
+pub enum LoadState {
+    Pending,
+    Loading { bytes_loaded: u64, total_bytes: u64 },
+    Done(Box<LoadedFile>),
+    Failed(String),
+    Cancelled,
+}
+
+struct JobShared {
+    state: LoadState,
+    preview: Option<Rope>, // Start of the text, shown while the rest loads
+    preview_shown: bool, // The UI has put a preview in the document
+}
+
+// One file being read on a background thread. The UI thread and the loader
+// share it; the loader reports progress and the result, the UI polls it and
+// can ask the loader to stop.
+#[derive(Clone)]
+pub struct LoadJob {
+    pub filename: String,
+    shared: Arc<Mutex<JobShared>>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl LoadJob {
+    pub fn new(filename: String) -> Self {
+        Self {
+            filename,
+            shared: Arc::new(Mutex::new(JobShared {
+                state: LoadState::Pending,
+                preview: None,
+                preview_shown: false,
+            })),
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // A job that failed before a thread was started, so the error shows up like any other
+    pub fn failed(filename: String, error: io::Error) -> Self {
+        let job = Self::new(filename);
+        job.finish(Err(error));
+        job
+    }
+
+    // Pending or Loading
+    pub fn is_active(&self) -> bool {
+        self.shared
+            .lock()
+            .map(|shared| matches!(shared.state, LoadState::Pending | LoadState::Loading { .. }))
+            .unwrap_or(false)
+    }
+
+    pub fn progress(&self) -> Option<(u64, u64)> {
+        match self.shared.lock().ok()?.state {
+            LoadState::Loading { bytes_loaded, total_bytes } => Some((bytes_loaded, total_bytes)),
+            _ => None,
+        }
+    }
+
+    pub fn error(&self) -> Option<String> {
+        match &self.shared.lock().ok()?.state {
+            LoadState::Failed(message) => Some(message.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        if let Ok(mut shared) = self.shared.lock() {
+            if matches!(shared.state, LoadState::Pending | LoadState::Loading { .. }) {
+                shared.state = LoadState::Cancelled;
+            }
+            shared.preview = None;
+        }
+    }
+
+    pub fn take_preview(&self) -> Option<Rope> {
+        let mut shared = self.shared.lock().ok()?;
+        let preview = shared.preview.take();
+        shared.preview_shown |= preview.is_some();
+        preview
+    }
+
+    pub fn preview_shown(&self) -> bool {
+        self.shared.lock().map(|shared| shared.preview_shown).unwrap_or(false)
+    }
+
+    // Hands the loaded file over; the job is of no further use afterwards
+    pub fn take_loaded(&self) -> Option<LoadedFile> {
+        let mut shared = self.shared.lock().ok()?;
+        if !matches!(shared.state, LoadState::Done(_)) {
+            return None;
+        }
+        match std::mem::replace(&mut shared.state, LoadState::Cancelled) {
+            LoadState::Done(loaded) => Some(*loaded),
+            _ => None,
+        }
+    }
+
+    // Called by the loader thread
+
+    pub fn set_progress(&self, bytes_loaded: u64, total_bytes: u64) {
+        if let Ok(mut shared) = self.shared.lock() {
+            if matches!(shared.state, LoadState::Pending | LoadState::Loading { .. }) {
+                shared.state = LoadState::Loading { bytes_loaded, total_bytes };
+            }
+        }
+    }
+
+    pub fn set_preview(&self, preview: Rope) {
+        if let Ok(mut shared) = self.shared.lock() {
+            if !self.is_cancelled() {
+                shared.preview = Some(preview);
+            }
+        }
+    }
+
+    // Whatever the loader returns after being cancelled is dropped
+    pub fn finish(&self, result: io::Result<LoadedFile>) {
+        if let Ok(mut shared) = self.shared.lock() {
+            if self.is_cancelled() {
+                return;
+            }
+            shared.state = match result {
+                Ok(loaded) => LoadState::Done(Box::new(loaded)),
+                Err(error) => LoadState::Failed(describe_error(&self.filename, &error)),
+            };
+        }
+    }
+}
+
+fn describe_error(filename: &str, error: &io::Error) -> String {
+    let reason = match error.kind() {
+        io::ErrorKind::NotFound => "file not found".to_string(),
+        io::ErrorKind::PermissionDenied => "permission denied".to_string(),
+        io::ErrorKind::IsADirectory => "it is a directory".to_string(),
+        _ => error.to_string(),
+    };
+    format!("Can't open {}: {}", filename, reason)
+}
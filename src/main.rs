@@ -1,12 +1,36 @@
 use eframe::egui;
-#[cfg(any(windows, target_os = "macos"))]
 use std::env;
 
 mod app;
 mod ui;
 mod file_ops;
 mod platform;
+mod document;
+mod undo;
+mod decoder;
+mod encoding;
+mod dialogs;
+mod line_ending;
+mod large_file;
+mod hex_editor;
+mod load_job;
+mod actions;
+mod atomic_write;
+mod notifications;
+mod recovery;
+mod diff_view;
+mod external_change;
+mod tabs;
+mod session;
+mod history;
+mod compression;
+mod read_only;
+mod find;
+mod find_in_files;
+mod replace_in_files;
+mod goto;
 
+use actions::actions_from_args;
 use app::TextEditorApp;
 
 fn main() -> Result<(), eframe::Error> {
@@ -25,47 +49,17 @@ fn main() -> Result<(), eframe::Error> {
         }
     }
     
-    // Check for command line arguments
-    #[cfg(windows)]
-    {
-        let args: Vec<String> = env::args().collect();
-        if args.len() > 1 {
-            let file_path = &args[1];
-            let mut app = TextEditorApp::default();
-            
-            // Store the file path to load after UI is ready
-            app.pending_file_to_load = Some(file_path.to_string());
-            
-            return eframe::run_native(
-                "Amend Text Editor",
-                options,
-                Box::new(|_cc| Ok(Box::new(app))),
-            );
-        }
-    }
-    
-    // Check for command line arguments on macOS
-    #[cfg(target_os = "macos")]
-    {
-        let args: Vec<String> = env::args().collect();
-        if args.len() > 1 {
-            let file_path = &args[1];
-            let mut app = TextEditorApp::default();
-            
-            // Store the file path to load after UI is ready
-            app.pending_file_to_load = Some(file_path.to_string());
-            
-            return eframe::run_native(
-                "Amend Text Editor",
-                options,
-                Box::new(|_cc| Ok(Box::new(app))),
-            );
-        }
+    // Files and commands from the command line run once the UI is ready
+    let mut app = TextEditorApp::default();
+    app.offer_recovery();
+    app.load_session();
+    for action in actions_from_args(env::args().skip(1)) {
+        app.queue_action(action);
     }
     
     eframe::run_native(
         "Amend Text Editor",
         options,
-        Box::new(|_cc| Ok(Box::new(TextEditorApp::default()))),
+        Box::new(|_cc| Ok(Box::new(app))),
     )
 } 
\ No newline at end of file
@@ -0,0 +1,96 @@
+use std::time::{Duration, Instant};
+use eframe::egui;
+use crate::actions::Action;
+
+// How long a notification that isn't an error stays up
+const INFO_TIMEOUT: Duration = Duration::from_secs(4);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Error,
+}
+
+pub struct Notification {
+    id: u64,
+    pub message: String,
+    pub severity: Severity,
+    pub actions: Vec<(String, Action)>, // Buttons offered with the message
+    shown_at: Instant,
+}
+
+// Toasts in the bottom right corner. Errors stay until dismissed or acted
+// on; anything else goes away by itself.
+#[derive(Default)]
+pub struct Notifications {
+    items: Vec<Notification>,
+    next_id: u64,
+}
+
+impl Notifications {
+    pub fn info(&mut self, message: impl Into<String>) {
+        self.push(message.into(), Severity::Info, Vec::new());
+    }
+
+    pub fn error(&mut self, message: impl Into<String>, actions: Vec<(String, Action)>) {
+        self.push(message.into(), Severity::Error, actions);
+    }
+
+    fn push(&mut self, message: String, severity: Severity, actions: Vec<(String, Action)>) {
+        // The same failure twice in a row shows once
+        self.items.retain(|n| n.message != message);
+        self.items.push(Notification {
+            id: self.next_id,
+            message,
+            severity,
+            actions,
+            shown_at: Instant::now(),
+        });
+        self.next_id += 1;
+    }
+
+    // Draws the toasts and returns the action of a button that was clicked
+    pub fn show(&mut self, ctx: &egui::Context) -> Option<Action> {
+        self.items
+            .retain(|n| n.severity == Severity::Error || n.shown_at.elapsed() < INFO_TIMEOUT);
+        if self.items.is_empty() {
+            return None;
+        }
+        if self.items.iter().any(|n| n.severity == Severity::Info) {
+            ctx.request_repaint_after(Duration::from_millis(250));
+        }
+
+        let mut clicked = None;
+        let mut dismissed = Vec::new();
+        egui::Area::new(egui::Id::new("notifications"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, [-12.0, -12.0])
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                ui.set_max_width(380.0);
+                for notification in &self.items {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        let color = match notification.severity {
+                            Severity::Error => ui.visuals().error_fg_color,
+                            Severity::Info => ui.visuals().text_color(),
+                        };
+                        ui.colored_label(color, &notification.message);
+                        ui.horizontal(|ui| {
+                            for (label, action) in &notification.actions {
+                                if ui.button(label).clicked() {
+                                    clicked = Some(action.clone());
+                                    dismissed.push(notification.id);
+                                }
+                            }
+                            if ui.button("Dismiss").clicked() {
+                                dismissed.push(notification.id);
+                            }
+                        });
+                    });
+                    ui.add_space(6.0);
+                }
+            });
+
+        self.items.retain(|n| !dismissed.contains(&n.id));
+        clicked
+    }
+}
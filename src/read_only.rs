@@ -0,0 +1,79 @@
+use std::fs::OpenOptions;
+use std::io;
+use eframe::egui;
+use crate::actions::Command;
+use crate::app::TextEditorApp;
+
+// Why the file can't be written, if it can't. Opening it for writing (without
+// truncating) catches missing permissions and read-only mounts alike. A file
+// that doesn't exist yet is fine, saving creates it.
+pub fn write_blocker(path: &str) -> Option<String> {
+    match OpenOptions::new().write(true).open(path) {
+        Ok(_) => None,
+        Err(error) => match error.kind() {
+            io::ErrorKind::NotFound => None,
+            io::ErrorKind::PermissionDenied => Some("you don't have permission to write to it".to_string()),
+            io::ErrorKind::ReadOnlyFilesystem => Some("it is on a read-only file system".to_string()),
+            _ => Some(error.to_string()),
+        },
+    }
+}
+
+impl TextEditorApp {
+    pub fn toggle_read_only(&mut self) {
+        if self.large_file.is_none() {
+            self.read_only = !self.read_only;
+        }
+    }
+
+    // Checks again before saving, the permissions may have changed since
+    // loading. Offers Save As instead of a save that is bound to fail.
+    pub fn check_writable(&mut self) -> bool {
+        let Some(filename) = self.filename.clone() else {
+            return true;
+        };
+        self.write_error = write_blocker(&filename);
+        let Some(reason) = &self.write_error else {
+            return true;
+        };
+        self.notifications.error(
+            format!("Can't save {}: {}", filename, reason),
+            vec![("Save As…".to_string(), self.in_this_tab(Command::SaveAs))],
+        );
+        false
+    }
+
+    // Bar under the menu saying why the document can't be edited or saved
+    pub fn show_read_only_bar(&mut self, ui: &mut egui::Ui) {
+        if self.large_file.is_some() || (!self.read_only && self.write_error.is_none()) {
+            return;
+        }
+        let mut save_as = false;
+        let mut toggle = false;
+        ui.separator();
+        ui.horizontal(|ui| {
+            let color = ui.visuals().warn_fg_color;
+            match (&self.write_error, self.read_only) {
+                (Some(reason), true) => {
+                    ui.colored_label(color, format!("Read-only: {}", reason));
+                    save_as = ui.button("Save As Elsewhere…").clicked();
+                    toggle = ui.button("Edit Anyway").on_hover_text("Changes can only be saved to another file").clicked();
+                }
+                (Some(reason), false) => {
+                    ui.colored_label(color, format!("Can't be saved here: {}", reason));
+                    save_as = ui.button("Save As Elsewhere…").clicked();
+                }
+                (None, _) => {
+                    ui.colored_label(color, "Read-only view");
+                    toggle = ui.button("Allow Editing").clicked();
+                }
+            }
+        });
+        if save_as {
+            self.save_file_as();
+        }
+        if toggle {
+            self.toggle_read_only();
+        }
+    }
+}
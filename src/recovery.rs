@@ -0,0 +1,390 @@
+use std::fs::{self, File, TryLockError};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use eframe::egui;
+use encoding_rs::Encoding;
+use ropey::Rope;
+use similar::ChangeTag;
+use crate::app::TextEditorApp;
+use crate::atomic_write::write_atomic;
+use crate::compression::{decompress_if_compressed, Codec};
+use crate::diff_view::{diff_lines, show_diff};
+use crate::document::Document;
+use crate::encoding::TextEncoding;
+use crate::line_ending::LineEndingStats;
+
+// How often a modified document is copied to its swap file
+pub const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+// Swap files that don't say which process wrote them are taken to be left
+// behind once they haven't been rewritten for this long
+const STALE_AFTER: Duration = Duration::from_secs(90);
+
+const HEADER: &str = "AMEND-RECOVERY 1";
+
+fn recovery_dir() -> Option<PathBuf> {
+    Some(dirs::data_dir()?.join("amend").join("recovery"))
+}
+
+fn lock_path(dir: &Path, pid: u32) -> PathBuf {
+    dir.join(format!("{}.lock", pid))
+}
+
+// Each running instance keeps "<pid>.lock" in the recovery directory locked
+// until it exits, so other instances can tell its swap files aren't orphans
+// even when it's idle. The lock goes with the process, however it stops.
+// Returns false if it couldn't be taken.
+fn hold_instance_lock(dir: &Path) -> bool {
+    static LOCK: OnceLock<Option<File>> = OnceLock::new();
+    LOCK.get_or_init(|| {
+        fs::create_dir_all(dir).ok()?;
+        let file = File::create(lock_path(dir, std::process::id())).ok()?;
+        file.try_lock().ok()?;
+        Some(file)
+    }).is_some()
+}
+
+// Whether the instance with this process id is still running, or None if
+// that can't be told. A stopped instance's lock file is removed on the way.
+fn is_running(dir: &Path, pid: u32) -> Option<bool> {
+    if pid == std::process::id() {
+        return Some(true);
+    }
+    let path = lock_path(dir, pid);
+    // Swap files only name their process while it holds the lock
+    let Ok(file) = File::open(&path) else {
+        return Some(false);
+    };
+    match file.try_lock() {
+        Ok(()) => {
+            drop(file);
+            let _ = fs::remove_file(path);
+            Some(false)
+        }
+        Err(TryLockError::WouldBlock) => Some(true),
+        Err(TryLockError::Error(_)) => None,
+    }
+}
+
+// The swap file of one document, shared with the threads writing it
+#[derive(Default)]
+struct SwapFile {
+    path: Option<PathBuf>,
+    discards: u64, // Writes started before the latest discard are dropped
+}
+
+// Keeps a copy of the unsaved text of one document in the recovery
+// directory. The copy is removed again once the document is saved or closed.
+#[derive(Default)]
+pub struct Autosave {
+    swap: Arc<Mutex<SwapFile>>, // Also locked while writing, so a discard can't race a write
+    last_write: Option<Instant>,
+}
+
+impl Autosave {
+    pub fn is_due(&self) -> bool {
+        self.last_write.is_none_or(|t| t.elapsed() >= AUTOSAVE_INTERVAL)
+    }
+
+    // Writes the snapshot on a background thread
+    pub fn write(&mut self, rope: Rope, original: Option<String>, encoding: TextEncoding) {
+        self.last_write = Some(Instant::now());
+        if let Some(dir) = recovery_dir() {
+            self.write_in(dir, rope, original, encoding);
+        }
+    }
+
+    fn write_in(&mut self, dir: PathBuf, rope: Rope, original: Option<String>, encoding: TextEncoding) -> thread::JoinHandle<()> {
+        let locked = hold_instance_lock(&dir);
+        let discards = self.swap.lock().map_or(0, |swap| swap.discards);
+        let swap = Arc::clone(&self.swap);
+        thread::spawn(move || {
+            let Ok(mut swap) = swap.lock() else {
+                return;
+            };
+            // Saved or closed since, so there is nothing to keep any more
+            if swap.discards != discards {
+                return;
+            }
+            let path = swap.path.get_or_insert_with(|| {
+                let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+                dir.join(format!("{}-{}.swap", std::process::id(), nanos))
+            });
+            let _ = fs::create_dir_all(&dir);
+            let _ = write_atomic(path, |out| {
+                writeln!(out, "{}", HEADER)?;
+                if locked {
+                    writeln!(out, "pid: {}", std::process::id())?;
+                }
+                writeln!(out, "path: {}", original.as_deref().unwrap_or(""))?;
+                writeln!(out, "encoding: {}", encoding.encoding.name())?;
+                writeln!(out, "bom: {}", encoding.bom)?;
+                writeln!(out)?;
+                for chunk in rope.chunks() {
+                    out.write_all(chunk.as_bytes())?;
+                }
+                Ok(())
+            });
+        })
+    }
+
+    pub fn discard(&mut self) {
+        self.last_write = None;
+        if let Ok(mut swap) = self.swap.lock() {
+            swap.discards += 1;
+            if let Some(path) = swap.path.take() {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+// Unsaved text found in the recovery directory at startup
+pub struct RecoveredFile {
+    pub swap_path: PathBuf,
+    pub original: Option<String>, // None for a document that was never saved
+    pub pid: Option<u32>, // Of the instance that wrote it
+    pub encoding: TextEncoding,
+    pub modified: SystemTime,
+    pub text: String,
+}
+
+impl RecoveredFile {
+    fn read(swap_path: PathBuf) -> io::Result<Self> {
+        let modified = fs::metadata(&swap_path)?.modified()?;
+        let contents = fs::read_to_string(&swap_path)?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not a recovery file");
+        let (header, text) = contents.split_once("\n\n").ok_or_else(invalid)?;
+        let mut lines = header.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(invalid());
+        }
+        let mut original = None;
+        let mut pid = None;
+        let mut encoding = TextEncoding::UTF8;
+        for line in lines {
+            match line.split_once(": ").unwrap_or((line.trim_end_matches(':'), "")) {
+                ("path", path) if !path.is_empty() => original = Some(path.to_string()),
+                ("pid", id) => pid = id.parse().ok(),
+                ("encoding", name) => {
+                    if let Some(found) = Encoding::for_label(name.as_bytes()) {
+                        encoding.encoding = found;
+                    }
+                }
+                ("bom", bom) => encoding.bom = bom == "true",
+                _ => {}
+            }
+        }
+        Ok(Self {
+            swap_path,
+            original,
+            pid,
+            encoding,
+            modified,
+            text: text.to_string(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        self.original.as_deref().unwrap_or("Untitled")
+    }
+
+    // The file as it is on disk now, empty if it's gone or was never saved
+    pub fn disk_text(&self) -> String {
+        let Some(bytes) = self.original.as_ref().and_then(|path| fs::read(path).ok()) else {
+            return String::new();
+        };
+        let bytes = decompress_if_compressed(bytes);
+        let bytes = bytes.strip_prefix(self.encoding.bom_bytes()).unwrap_or(&bytes);
+        self.encoding.encoding.decode_without_bom_handling(bytes).0.into_owned()
+    }
+}
+
+// Swap files that no running instance is keeping up to date
+pub fn find_recoverable() -> Vec<RecoveredFile> {
+    recovery_dir().map_or_else(Vec::new, |dir| find_recoverable_in(&dir))
+}
+
+fn find_recoverable_in(dir: &Path) -> Vec<RecoveredFile> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let stale = |file: &RecoveredFile| file.modified.elapsed().is_ok_and(|age| age > STALE_AFTER);
+    let mut found: Vec<RecoveredFile> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "swap"))
+        .filter_map(|path| RecoveredFile::read(path).ok())
+        .filter(|file| match file.pid.and_then(|pid| is_running(dir, pid)) {
+            Some(running) => !running,
+            None => stale(file),
+        })
+        .collect();
+    found.sort_by_key(|file| std::cmp::Reverse(file.modified));
+    found
+}
+
+// Writes the document's swap file when it's due, or removes it once there is
+// nothing unsaved. Returns true if the document has a swap file to keep up.
+fn autosave_document(document: &Document, filename: &Option<String>, autosave: &mut Autosave) -> bool {
+    // A partial document can't stand in for its whole file, so it isn't kept
+    if !document.is_modified() || document.partial().is_some() {
+        autosave.discard();
+        return false;
+    }
+    if autosave.is_due() {
+        autosave.write(document.snapshot(), filename.clone(), document.encoding());
+    }
+    true
+}
+
+pub struct RecoveryDialog {
+    pub files: Vec<RecoveredFile>,
+    pub diff: Option<(usize, Vec<(ChangeTag, String)>)>, // Index of the file being compared and its diff
+}
+
+impl TextEditorApp {
+    pub fn offer_recovery(&mut self) {
+        let files = find_recoverable();
+        if !files.is_empty() {
+            self.recovery_dialog = Some(RecoveryDialog { files, diff: None });
+        }
+    }
+
+    // Keeps the swap file of every open document up to date, not just the
+    // one in front
+    pub fn autosave(&mut self, ctx: &egui::Context) {
+        let mut kept = autosave_document(&self.document, &self.filename, &mut self.autosave);
+        for (i, tab) in self.tabs.iter_mut().enumerate() {
+            if i != self.active_tab {
+                kept |= autosave_document(&tab.document, &tab.filename, &mut tab.autosave);
+            }
+        }
+        if kept {
+            ctx.request_repaint_after(AUTOSAVE_INTERVAL);
+        }
+    }
+
+    fn restore(&mut self, file: RecoveredFile) {
+        // Start from the file on disk so that undo goes back to it
+        let mut document = self.new_document(Rope::from_str(&file.disk_text()));
+        document.replace_all(&file.text);
+        document.set_encoding(file.encoding);
+        document.set_compression(file.original.as_ref().and_then(Codec::from_extension));
+        let mut line_endings = LineEndingStats::default();
+        line_endings.feed(&file.text);
+        line_endings.finish();
+        document.set_line_endings(&line_endings);
+
+        if !self.is_blank_tab() {
+            self.new_tab();
+        }
+        self.document = document;
+        self.filename = file.original;
+        let _ = fs::remove_file(&file.swap_path);
+    }
+
+    pub fn show_recovery_dialog(&mut self, ctx: &egui::Context) {
+        let Some(dialog) = &mut self.recovery_dialog else {
+            return;
+        };
+        let mut restore = None;
+        let mut discard = None;
+        let mut compare = None;
+        let mut close = false;
+
+        egui::Modal::new(egui::Id::new("recovery")).show(ctx, |ui| {
+            ui.heading("Recover Unsaved Work");
+            ui.label("These documents had unsaved changes when Amend last stopped unexpectedly.");
+            ui.add_space(8.0);
+            for (i, file) in dialog.files.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    let age = file.modified.elapsed().map(|d| d.as_secs() / 60).unwrap_or(0);
+                    ui.label(format!("{} ({} minutes ago)", file.name(), age));
+                    if ui.button("Restore").clicked() {
+                        restore = Some(i);
+                    }
+                    if ui.button("Compare").clicked() {
+                        compare = Some(i);
+                    }
+                    if ui.button("Discard").clicked() {
+                        discard = Some(i);
+                    }
+                });
+            }
+
+            if let Some((i, changes)) = &dialog.diff {
+                ui.separator();
+                ui.label(format!("Changes in {} compared to the file on disk:", dialog.files[*i].name()));
+                show_diff(ui, changes);
+            }
+
+            ui.separator();
+            if ui.button("Close").clicked() {
+                close = true;
+            }
+        });
+
+        if let Some(i) = compare {
+            dialog.diff = Some((i, diff_lines(&dialog.files[i].disk_text(), &dialog.files[i].text)));
+        }
+        if let Some(i) = restore.or(discard) {
+            dialog.diff = None;
+            let file = dialog.files.remove(i);
+            if restore.is_some() {
+                // Each restored document gets a tab of its own
+                self.restore(file);
+            } else {
+                let _ = fs::remove_file(&file.swap_path);
+            }
+        }
+        if close || self.recovery_dialog.as_ref().is_some_and(|d| d.files.is_empty()) {
+            self.recovery_dialog = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn swap_files(dir: &Path) -> Vec<PathBuf> {
+        fs::read_dir(dir).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "swap"))
+            .collect()
+    }
+
+    #[test]
+    fn write_discard_and_recover() {
+        let dir = std::env::temp_dir().join(format!("amend-recovery-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut autosave = Autosave::default();
+        autosave.write_in(dir.clone(), Rope::from_str("unsaved\ntext"), Some("/tmp/notes.txt".to_string()), TextEncoding::UTF8)
+            .join().unwrap();
+        let written = swap_files(&dir);
+        assert_eq!(written.len(), 1);
+        let file = RecoveredFile::read(written[0].clone()).unwrap();
+        assert_eq!((file.text.as_str(), file.original.as_deref(), file.pid), ("unsaved\ntext", Some("/tmp/notes.txt"), Some(std::process::id())));
+        // This instance is still running, so it isn't offered
+        assert!(find_recoverable_in(&dir).is_empty());
+
+        // Once the writer is gone it is
+        let orphaned = fs::read_to_string(&written[0]).unwrap().replace(&format!("pid: {}", std::process::id()), "pid: 4294967295");
+        fs::write(&written[0], orphaned).unwrap();
+        assert_eq!(find_recoverable_in(&dir).len(), 1);
+
+        autosave.discard();
+        assert!(swap_files(&dir).is_empty());
+
+        // A discard right after a write wins, whichever runs first
+        let writer = autosave.write_in(dir.clone(), Rope::from_str("more"), None, TextEncoding::UTF8);
+        autosave.discard();
+        writer.join().unwrap();
+        assert!(swap_files(&dir).is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
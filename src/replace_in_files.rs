@@ -0,0 +1,397 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use eframe::egui;
+use encoding_rs::Encoding;
+use ropey::Rope;
+use similar::ChangeTag;
+use crate::app::TextEditorApp;
+use crate::atomic_write::write_file;
+use crate::diff_view::show_diff_line;
+use crate::document::Document;
+use crate::encoding::TextEncoding;
+use crate::find::{build_text_regex, preview_lines, replacements};
+use crate::find_in_files::MAX_HITS;
+use crate::read_only::write_blocker;
+
+// One match in a file and what it becomes
+pub struct ReviewHit {
+    pub range: Range<usize>, // Bytes of the file's text
+    pub replacement: String,
+    pub include: bool,
+    pub line: usize, // 1-based, for its checkbox
+}
+
+// Where a file's text comes from and goes back to
+pub enum Source {
+    Tab { id: u64, revision: u64 }, // Open in Amend; its document is changed and left unsaved
+    Disk { original: Vec<u8>, encoding: TextEncoding }, // Written back in the encoding it was read in
+}
+
+pub struct ReviewFile {
+    pub path: PathBuf,
+    pub source: Source,
+    pub text: String,
+    pub hits: Vec<ReviewHit>,
+    pub diff: Vec<(ChangeTag, String)>, // The included hits' lines before and after
+}
+
+impl ReviewFile {
+    // Made again whenever a hit is ticked or unticked. One pass over the
+    // text, however many hits there are.
+    fn update_diff(&mut self) {
+        let included: Vec<(Range<usize>, String)> = self.hits.iter()
+            .filter(|hit| hit.include)
+            .map(|hit| (hit.range.clone(), hit.replacement.clone()))
+            .collect();
+        self.diff = preview_lines(&self.text, &included);
+    }
+}
+
+// The replacements across files, for going through before they are made
+pub struct ReplaceReview {
+    pub files: Vec<ReviewFile>,
+    pub skipped: Vec<String>, // Files that can't be changed, and why
+    pub truncated: bool, // Stopped at MAX_HITS
+}
+
+// What a replace in files changed, so it can be put back
+pub enum Replaced {
+    Document { tab: u64, path: PathBuf, revision: u64 }, // Revision right after the replace, as one undo step
+    Disk { path: PathBuf, original: Vec<u8>, written: Vec<u8> },
+}
+
+// A file that isn't open as text and how to write it back. Files with a BOM
+// are decoded by it; anything else has to be valid UTF-8, so nothing is
+// lost by writing it again.
+fn decode_for_replace(bytes: &[u8]) -> Result<(String, TextEncoding), &'static str> {
+    if let Some((encoding, bom_len)) = Encoding::for_bom(bytes) {
+        let text = encoding.decode_without_bom_handling(&bytes[bom_len..]).0.into_owned();
+        return Ok((text, TextEncoding { encoding, bom: true }));
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Ok((text.to_string(), TextEncoding::UTF8)),
+        Err(_) => Err("isn't valid UTF-8; open it in Amend to replace in it"),
+    }
+}
+
+// The text with the included hits replaced
+fn splice(text: &str, hits: &[ReviewHit]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut pos = 0;
+    for hit in hits.iter().filter(|hit| hit.include) {
+        out.push_str(&text[pos..hit.range.start]);
+        out.push_str(&hit.replacement);
+        pos = hit.range.end;
+    }
+    out.push_str(&text[pos..]);
+    out
+}
+
+// Shows each file's diff with a checkbox for every hit. Returns Some(true) to
+// apply the included hits and Some(false) to go back to the search results.
+pub fn show_review(ui: &mut egui::Ui, review: &mut ReplaceReview) -> Option<bool> {
+    let mut choice = None;
+    let total: usize = review.files.iter().map(|file| file.hits.len()).sum();
+    let included: usize = review.files.iter().map(|file| file.hits.iter().filter(|hit| hit.include).count()).sum();
+    ui.horizontal(|ui| {
+        ui.label(format!("{} of {} replacements selected", included, total));
+        if ui.add_enabled(included > 0, egui::Button::new("Apply")).clicked() {
+            choice = Some(true);
+        }
+        if ui.button("Cancel").clicked() {
+            choice = Some(false);
+        }
+    });
+    if review.truncated {
+        ui.colored_label(ui.visuals().warn_fg_color, format!("Stopped after {} replacements; review again for the rest", MAX_HITS));
+    }
+    for skipped in &review.skipped {
+        ui.colored_label(ui.visuals().warn_fg_color, skipped);
+    }
+    egui::ScrollArea::both().id_salt("replace_in_files_review").auto_shrink([false, false]).show(ui, |ui| {
+        for file in &mut review.files {
+            let included = file.hits.iter().filter(|hit| hit.include).count();
+            let open = if matches!(file.source, Source::Tab { .. }) { ", open" } else { "" };
+            egui::CollapsingHeader::new(format!("{} ({}/{}{})", file.path.display(), included, file.hits.len(), open))
+                .id_salt(("review", &file.path))
+                .default_open(true)
+                .show(ui, |ui| {
+                    let mut changed = false;
+                    ui.horizontal_wrapped(|ui| {
+                        let all = ui.small_button("All").clicked();
+                        if all || ui.small_button("None").clicked() {
+                            for hit in &mut file.hits {
+                                hit.include = all;
+                            }
+                            changed = true;
+                        }
+                        for hit in &mut file.hits {
+                            changed |= ui.checkbox(&mut hit.include, format!("line {}", hit.line)).changed();
+                        }
+                    });
+                    if changed {
+                        file.update_diff();
+                    }
+                    for (tag, line) in &file.diff {
+                        show_diff_line(ui, *tag, line);
+                    }
+                });
+        }
+    });
+    choice
+}
+
+impl TextEditorApp {
+    // The tab the file is open in, compared by canonical path
+    pub fn tab_with_path(&self, path: &Path) -> Option<usize> {
+        (0..self.tabs.len()).find(|&i| {
+            let filename = if i == self.active_tab { &self.filename } else { &self.tabs[i].filename };
+            filename.as_ref().and_then(|filename| fs::canonicalize(filename).ok()).as_deref() == Some(path)
+        })
+    }
+
+    // Why the tab's document can't be replaced in, if it can't
+    fn tab_blocker(&self, index: usize) -> Option<&'static str> {
+        let tab = &self.tabs[index];
+        let (large_file, hex_editor, read_only, loading) = if index == self.active_tab {
+            (self.large_file.is_some(), self.hex_editor.is_some(), self.read_only, self.is_loading())
+        } else {
+            (tab.large_file.is_some(), tab.hex_editor.is_some(), tab.read_only, tab.load_job.as_ref().is_some_and(|job| job.is_active()))
+        };
+        if loading {
+            Some("is still loading")
+        } else if large_file || hex_editor {
+            Some("is open in a viewer that can't edit text")
+        } else if read_only {
+            Some("is open read-only")
+        } else {
+            None
+        }
+    }
+
+    fn tab_document(&self, id: u64) -> Option<&Document> {
+        let index = self.tabs.iter().position(|tab| tab.id == id)?;
+        Some(if index == self.active_tab { &self.document } else { &self.tabs[index].document })
+    }
+
+    fn tab_document_mut(&mut self, id: u64) -> Option<&mut Document> {
+        let index = self.tabs.iter().position(|tab| tab.id == id)?;
+        Some(if index == self.active_tab { &mut self.document } else { &mut self.tabs[index].document })
+    }
+
+    // Works out the replacements in every file the search found something in.
+    // Open files are searched again as they are in the editor.
+    pub fn review_replace_in_files(&mut self) {
+        let Some(panel) = &self.files_panel else {
+            return;
+        };
+        let Ok(regex) = build_text_regex(&panel.query, panel.options) else {
+            return;
+        };
+        let (template, options) = (panel.replacement.clone(), panel.options);
+        let paths: Vec<PathBuf> = panel.search.as_ref()
+            .and_then(|search| search.results())
+            .map(|results| results.files.iter().map(|file| file.path.clone()).collect())
+            .unwrap_or_default();
+
+        let mut review = ReplaceReview { files: Vec::new(), skipped: Vec::new(), truncated: false };
+        let mut found = 0;
+        for path in paths {
+            if found >= MAX_HITS {
+                review.truncated = true;
+                break;
+            }
+            let (text, source) = if let Some(index) = self.tab_with_path(&path) {
+                if let Some(reason) = self.tab_blocker(index) {
+                    review.skipped.push(format!("{} {}", path.display(), reason));
+                    continue;
+                }
+                let id = self.tabs[index].id;
+                let Some(document) = self.tab_document(id) else {
+                    continue;
+                };
+                (egui::TextBuffer::as_str(document).to_string(), Source::Tab { id, revision: document.revision() })
+            } else {
+                // Writing would replace an unwritable file without asking, the way a rename can
+                if let Some(reason) = write_blocker(&path.display().to_string()) {
+                    review.skipped.push(format!("{} can't be changed: {}", path.display(), reason));
+                    continue;
+                }
+                let decoded = fs::read(&path)
+                    .map_err(|error| error.to_string())
+                    .and_then(|bytes| decode_for_replace(&bytes).map(|decoded| (bytes, decoded)).map_err(str::to_string));
+                match decoded {
+                    Ok((original, (text, encoding))) => (text, Source::Disk { original, encoding }),
+                    Err(error) => {
+                        review.skipped.push(format!("{} {}", path.display(), error));
+                        continue;
+                    }
+                }
+            };
+            // Hits come in order, so their lines are counted on from the one before
+            let mut line = 1;
+            let mut counted = 0;
+            let hits: Vec<ReviewHit> = replacements(&text, &regex, &template, options, 0..text.len(), MAX_HITS - found)
+                .into_iter()
+                .map(|(range, replacement)| {
+                    line += text[counted..range.start].bytes().filter(|&b| b == b'\n').count();
+                    counted = range.start;
+                    ReviewHit { range, replacement, include: true, line }
+                })
+                .collect();
+            found += hits.len();
+            if found >= MAX_HITS {
+                review.truncated = true;
+            }
+            if !hits.is_empty() {
+                let mut file = ReviewFile { path, source, text, hits, diff: Vec::new() };
+                file.update_diff();
+                review.files.push(file);
+            }
+        }
+        if let Some(panel) = &mut self.files_panel {
+            panel.review = Some(review);
+        }
+    }
+
+    // Makes the included replacements: open documents change in memory as one
+    // undo step each, other files are written the way a save writes them.
+    // Files that changed since the review are left alone.
+    pub fn apply_replace_in_files(&mut self) {
+        let Some(review) = self.files_panel.as_mut().and_then(|panel| panel.review.take()) else {
+            return;
+        };
+        let mut replaced = Vec::new();
+        let mut count = 0;
+        let mut failed = Vec::new();
+        for file in review.files {
+            let hits: Vec<&ReviewHit> = file.hits.iter().filter(|hit| hit.include).collect();
+            if hits.is_empty() {
+                continue;
+            }
+            match file.source {
+                Source::Tab { id, revision } => {
+                    let Some(document) = self.tab_document_mut(id).filter(|document| document.revision() == revision) else {
+                        failed.push(format!("{} (changed since the review)", file.path.display()));
+                        continue;
+                    };
+                    let rope = document.rope();
+                    let edits: Vec<(Range<usize>, String)> = hits.iter()
+                        .map(|hit| (rope.byte_to_char(hit.range.start)..rope.byte_to_char(hit.range.end), hit.replacement.clone()))
+                        .collect();
+                    document.replace_ranges(&edits);
+                    replaced.push(Replaced::Document { tab: id, path: file.path, revision: document.revision() });
+                }
+                Source::Disk { original, encoding } => {
+                    if fs::read(&file.path).ok().as_ref() != Some(&original) {
+                        failed.push(format!("{} (changed on disk since the review)", file.path.display()));
+                        continue;
+                    }
+                    let mut written = Vec::new();
+                    let text = splice(&file.text, &file.hits);
+                    if let Err(error) = encoding.write_rope(&Rope::from_str(&text), &mut written) {
+                        failed.push(format!("{} ({})", file.path.display(), error));
+                        continue;
+                    }
+                    // Like a save, so what it was is in the local history too
+                    self.record_history_of(&file.path.display().to_string());
+                    if let Err(error) = write_file(&file.path, &written, self.save_in_place) {
+                        failed.push(format!("{} ({})", file.path.display(), error));
+                        continue;
+                    }
+                    replaced.push(Replaced::Disk { path: file.path, original, written });
+                }
+            }
+            count += hits.len();
+        }
+
+        if !failed.is_empty() {
+            self.notifications.error(format!("Nothing was replaced in {}", failed.join(", ")), Vec::new());
+        }
+        if !replaced.is_empty() {
+            self.notifications.info(format!("Replaced {} matches in {} files", count, replaced.len()));
+            self.replaced_in_files = replaced;
+        }
+        // The results are out of date now
+        if let Some(panel) = &mut self.files_panel {
+            panel.start_search();
+        }
+    }
+
+    // Puts back everything the last replace in files changed. Files and
+    // documents that were changed again since are left as they are.
+    pub fn undo_replace_in_files(&mut self, ctx: &egui::Context) {
+        let replaced = std::mem::take(&mut self.replaced_in_files);
+        if replaced.is_empty() {
+            return;
+        }
+        let mut failed = Vec::new();
+        for change in replaced {
+            match change {
+                Replaced::Document { tab, path, revision } => {
+                    match self.tab_document_mut(tab).filter(|document| document.revision() == revision) {
+                        Some(document) => {
+                            if let Some(cursor) = document.undo() {
+                                self.place_cursor_in_tab(ctx, tab, cursor);
+                            }
+                        }
+                        None => failed.push(path.display().to_string()),
+                    }
+                }
+                Replaced::Disk { path, original, written } => {
+                    let unchanged = fs::read(&path).ok().as_ref() == Some(&written);
+                    let writable = write_blocker(&path.display().to_string()).is_none();
+                    if !unchanged || !writable || write_file(&path, &original, self.save_in_place).is_err() {
+                        failed.push(path.display().to_string());
+                    }
+                }
+            }
+        }
+        if failed.is_empty() {
+            self.notifications.info("Replace in files undone");
+        } else {
+            self.notifications.error(format!("Changed again since, so not undone: {}", failed.join(", ")), Vec::new());
+        }
+        if let Some(panel) = &mut self.files_panel {
+            panel.start_search();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(range: Range<usize>, replacement: &str, include: bool) -> ReviewHit {
+        ReviewHit { range, replacement: replacement.to_string(), include, line: 1 }
+    }
+
+    #[test]
+    fn splice_replaces_only_included_hits() {
+        let text = "one two three two";
+        let hits = [hit(4..7, "2", true), hit(14..17, "II", false)];
+        assert_eq!(splice(text, &hits), "one 2 three two");
+        let hits = [hit(0..3, "", true), hit(14..17, "twos", true)];
+        assert_eq!(splice(text, &hits), " two three twos");
+        assert_eq!(splice(text, &[]), text);
+    }
+
+    #[test]
+    fn decodes_by_bom_or_as_utf8() {
+        let (text, encoding) = decode_for_replace("plain ü".as_bytes()).unwrap();
+        assert_eq!(text, "plain ü");
+        assert_eq!(encoding, TextEncoding::UTF8);
+
+        let (text, encoding) = decode_for_replace(b"\xEF\xBB\xBFwith bom").unwrap();
+        assert_eq!(text, "with bom");
+        assert_eq!(encoding, TextEncoding { encoding: encoding_rs::UTF_8, bom: true });
+
+        let (text, encoding) = decode_for_replace(b"\xFF\xFEh\0i\0").unwrap();
+        assert_eq!(text, "hi");
+        assert_eq!(encoding, TextEncoding { encoding: encoding_rs::UTF_16LE, bom: true });
+
+        // Latin-1 and the like would be changed by writing them back as UTF-8
+        assert!(decode_for_replace(b"caf\xE9").is_err());
+    }
+}
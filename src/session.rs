@@ -0,0 +1,317 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use eframe::egui;
+use crate::actions::Action;
+use crate::app::TextEditorApp;
+use crate::atomic_write::write_atomic;
+use crate::ui::EDITOR_ID;
+
+// How many files the Open Recent menu keeps besides the pinned ones
+const MAX_RECENT: usize = 10;
+
+const HEADER: &str = "AMEND-SESSION 1";
+
+fn session_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("amend").join("session"))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecentFile {
+    pub path: String,
+    pub pinned: bool, // Stays at the top and survives Clear
+}
+
+// A file that was open when Amend was last closed, and where the user was in it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionFile {
+    pub path: String,
+    pub line: usize, // 1-based, like GotoLine
+    pub column: usize,
+    pub scroll: (u32, u32), // Scroll offset of the editor in points
+}
+
+// What is kept between runs: the recent files, and the files that were open
+// at the last quit. Stored as a small text file in the config directory:
+//   AMEND-SESSION 1
+//   reopen: true
+//   active: 0
+//   recent: pinned /path/to/file
+//   recent: /path/to/other
+//   open: LINE COLUMN SCROLL_X SCROLL_Y /path/to/file
+#[derive(Default)]
+pub struct Session {
+    pub reopen: bool, // Open the last session's files again at startup
+    pub recent: Vec<RecentFile>,
+    pub open: Vec<SessionFile>,
+    pub active: usize, // Index into `open` of the tab that was in front
+}
+
+impl Session {
+    pub fn load() -> Self {
+        session_path().and_then(|path| Self::read(&path).ok()).unwrap_or_default()
+    }
+
+    fn read(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut lines = contents.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a session file"));
+        }
+        let mut session = Session::default();
+        for line in lines {
+            let Some((key, value)) = line.split_once(": ") else {
+                continue;
+            };
+            match key {
+                "reopen" => session.reopen = value == "true",
+                "active" => session.active = value.parse().unwrap_or(0),
+                "recent" => {
+                    let (pinned, path) = match value.strip_prefix("pinned ") {
+                        Some(path) => (true, path),
+                        None => (false, value),
+                    };
+                    session.recent.push(RecentFile { path: path.to_string(), pinned });
+                }
+                "open" => {
+                    let mut fields = value.splitn(5, ' ');
+                    let mut number = || fields.next().and_then(|n| n.parse::<u32>().ok());
+                    if let (Some(line), Some(column), Some(x), Some(y)) = (number(), number(), number(), number()) {
+                        if let Some(path) = fields.next() {
+                            session.open.push(SessionFile {
+                                path: path.to_string(),
+                                line: line as usize,
+                                column: column as usize,
+                                scroll: (x, y),
+                            });
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(session)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        match session_path() {
+            Some(path) => self.write(&path),
+            None => Ok(()),
+        }
+    }
+
+    fn write(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomic(path, |out| {
+            writeln!(out, "{}", HEADER)?;
+            writeln!(out, "reopen: {}", self.reopen)?;
+            writeln!(out, "active: {}", self.active)?;
+            for file in &self.recent {
+                let pin = if file.pinned { "pinned " } else { "" };
+                writeln!(out, "recent: {}{}", pin, file.path)?;
+            }
+            for file in &self.open {
+                writeln!(out, "open: {} {} {} {} {}", file.line, file.column, file.scroll.0, file.scroll.1, file.path)?;
+            }
+            Ok(())
+        })
+    }
+
+    // Moves the file to the top of the unpinned files, or leaves it where it
+    // is if it's pinned
+    pub fn add_recent(&mut self, path: &str) {
+        if self.recent.iter().any(|file| file.pinned && file.path == path) {
+            return;
+        }
+        self.recent.retain(|file| file.path != path);
+        let first_unpinned = self.recent.iter().position(|file| !file.pinned).unwrap_or(self.recent.len());
+        self.recent.insert(first_unpinned, RecentFile { path: path.to_string(), pinned: false });
+        let mut unpinned = 0;
+        self.recent.retain(|file| {
+            unpinned += usize::from(!file.pinned);
+            file.pinned || unpinned <= MAX_RECENT
+        });
+    }
+
+    // Pinned files are listed first
+    pub fn toggle_pin(&mut self, path: &str) {
+        let Some(index) = self.recent.iter().position(|file| file.path == path) else {
+            return;
+        };
+        let mut file = self.recent.remove(index);
+        file.pinned = !file.pinned;
+        let first_unpinned = self.recent.iter().position(|file| !file.pinned).unwrap_or(self.recent.len());
+        self.recent.insert(first_unpinned, file);
+    }
+
+    // Forgets everything that isn't pinned
+    pub fn clear_recent(&mut self) {
+        self.recent.retain(|file| file.pinned);
+    }
+}
+
+impl TextEditorApp {
+    pub fn add_recent(&mut self, path: &str) {
+        self.session.add_recent(path);
+        self.save_session();
+    }
+
+    pub fn save_session(&mut self) {
+        if let Err(error) = self.session.save() {
+            self.notifications.error(format!("Couldn't save the list of recent files: {}", error), Vec::new());
+        }
+    }
+
+    // Kept for the next start, whichever way the window was closed. There is
+    // nowhere left to show an error by then.
+    pub fn save_open_files(&mut self, (open, active): (Vec<SessionFile>, usize)) {
+        self.session.open = open;
+        self.session.active = active;
+        let _ = self.session.save();
+    }
+
+    // Where the user is in each open file, taken before quitting closes any tabs
+    pub fn session_files(&self, ctx: &egui::Context) -> (Vec<SessionFile>, usize) {
+        let mut files = Vec::new();
+        let mut active = 0;
+        for i in 0..self.tabs.len() {
+            let tab = &self.tabs[i];
+            let (filename, document, view) = if i == self.active_tab {
+                (&self.filename, &self.document, &self.view)
+            } else {
+                (&tab.filename, &tab.document, &tab.view)
+            };
+            let Some(path) = filename else {
+                continue;
+            };
+            let editor_id = egui::Id::new(EDITOR_ID).with(tab.id);
+            let cursor = egui::TextEdit::load_state(ctx, editor_id)
+                .and_then(|state| state.cursor.char_range())
+                .map_or(0, |range| range.primary.index);
+            let rope = document.rope();
+            let cursor = cursor.min(rope.len_chars());
+            let line = rope.char_to_line(cursor);
+            if i == self.active_tab {
+                active = files.len();
+            }
+            files.push(SessionFile {
+                path: path.clone(),
+                line: line + 1,
+                column: cursor - rope.line_to_char(line) + 1,
+                scroll: (view.offset.x.max(0.0) as u32, view.offset.y.max(0.0) as u32),
+            });
+        }
+        (files, active)
+    }
+
+    // Reads the saved session. If the user wants it, the files from the last
+    // session are queued, each followed by the cursor and scroll position it
+    // had, and then the tab that was in front.
+    pub fn load_session(&mut self) {
+        self.session = Session::load();
+        if !self.session.reopen {
+            return;
+        }
+        let files: Vec<SessionFile> = self.session.open.iter()
+            .filter(|file| Path::new(&file.path).is_file())
+            .cloned()
+            .collect();
+        for file in &files {
+            self.queue_action(Action::OpenFile(file.path.clone()));
+            self.queue_action(Action::GotoLine { line: file.line, column: Some(file.column) });
+            self.queue_action(Action::ScrollTo { x: file.scroll.0, y: file.scroll.1 });
+        }
+        if let Some(active) = self.session.open.get(self.session.active).filter(|file| files.contains(file)) {
+            self.queue_action(Action::SwitchToFile(active.path.clone()));
+        }
+    }
+
+    pub fn show_recent_menu(&mut self, ui: &mut egui::Ui) {
+        let mut open = None;
+        let mut pin = None;
+        if self.session.recent.is_empty() {
+            ui.label("No recent files");
+        }
+        for file in &self.session.recent {
+            ui.horizontal(|ui| {
+                let pin_label = if file.pinned { "Unpin" } else { "Pin" };
+                if ui.small_button(pin_label).clicked() {
+                    pin = Some(file.path.clone());
+                }
+                let mut label = egui::RichText::new(&file.path);
+                if file.pinned {
+                    label = label.strong();
+                }
+                if ui.button(label).clicked() {
+                    open = Some(file.path.clone());
+                }
+            });
+        }
+        ui.separator();
+        if ui.add_enabled(self.session.recent.iter().any(|f| !f.pinned), egui::Button::new("Clear Recent"))
+            .on_hover_text("Pinned files stay")
+            .clicked()
+        {
+            self.session.clear_recent();
+            self.save_session();
+        }
+
+        if let Some(path) = pin {
+            self.session.toggle_pin(&path);
+            self.save_session();
+        }
+        if let Some(path) = open {
+            self.queue_action(Action::OpenFile(path));
+            ui.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("amend-session-test-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = temp_path("round-trip");
+        let session = Session {
+            reopen: true,
+            recent: vec![
+                RecentFile { path: "/a b/pinned.txt".to_string(), pinned: true },
+                RecentFile { path: "/other.txt".to_string(), pinned: false },
+            ],
+            open: vec![SessionFile { path: "/with spaces/file.rs".to_string(), line: 12, column: 5, scroll: (0, 340) }],
+            active: 0,
+        };
+        session.write(&path).unwrap();
+        let read = Session::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(read.reopen);
+        assert_eq!(read.recent, session.recent);
+        assert_eq!(read.open, session.open);
+        assert_eq!(read.active, 0);
+    }
+
+    #[test]
+    fn missing_and_corrupt_files() {
+        assert!(Session::read(&temp_path("missing")).is_err());
+
+        let path = temp_path("corrupt");
+        fs::write(&path, "something else\nreopen: true\n").unwrap();
+        assert!(Session::read(&path).is_err());
+
+        // Lines that don't parse are skipped, the rest is kept
+        fs::write(&path, format!("{}\nopen: x 1 0 0 /bad\nopen: 3 1 0 0\nnonsense\nactive: many\nrecent: /kept\n", HEADER)).unwrap();
+        let read = Session::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(read.open.is_empty());
+        assert_eq!(read.active, 0);
+        assert_eq!(read.recent, [RecentFile { path: "/kept".to_string(), pinned: false }]);
+    }
+}
@@ -0,0 +1,347 @@
+use std::fs;
+use std::path::Path;
+use eframe::egui;
+use crate::actions::{Action, Command};
+use crate::app::TextEditorApp;
+use crate::document::Document;
+use crate::external_change::FileStamp;
+use crate::hex_editor::HexEditor;
+use crate::large_file::LargeFile;
+use crate::load_job::LoadJob;
+use crate::recovery::Autosave;
+use crate::ui::EDITOR_ID;
+
+// Everything that belongs to one open document. The active tab's state lives
+// in the fields of TextEditorApp, so its slot here only keeps its id; the two
+// are swapped whenever another tab is brought to the front.
+#[derive(Default)]
+pub struct Tab {
+    pub id: u64, // Keys the text edit and scroll state, so each tab keeps its cursor and scroll position
+    pub document: Document,
+    pub filename: Option<String>,
+    pub large_file: Option<LargeFile>,
+    pub hex_editor: Option<HexEditor>,
+    pub disk_stamp: Option<FileStamp>,
+    pub invalid_bytes: usize,
+    pub read_only: bool,
+    pub write_error: Option<String>,
+    pub autosave: Autosave,
+    pub load_job: Option<LoadJob>, // Keeps loading in the background while another tab is shown
+    pub view: EditorView,
+}
+
+// Scroll position of a tab's text editor
+#[derive(Clone, Copy, Default)]
+pub struct EditorView {
+    pub offset: egui::Vec2, // As of the last frame the tab was shown
+    pub scroll_to: Option<egui::Vec2>, // Applied the next time it's shown
+    pub select: Option<(usize, usize)>, // Char range to select and scroll into view the next time it's shown
+}
+
+impl Tab {
+    fn has_unsaved_changes(&self) -> bool {
+        self.document.is_modified() || self.hex_editor.as_ref().is_some_and(|hex| hex.modified)
+    }
+}
+
+// The name shown on a tab: just the file name, the full path is in the tooltip
+fn tab_name(filename: Option<&str>) -> String {
+    match filename {
+        Some(filename) => Path::new(filename)
+            .file_name()
+            .map_or_else(|| filename.to_string(), |name| name.to_string_lossy().into_owned()),
+        None => "Untitled".to_string(),
+    }
+}
+
+impl TextEditorApp {
+    // Exchanges the app's fields with the active tab's slot. Doing it twice
+    // puts everything back, which is how tabs are switched.
+    fn swap_active(&mut self) {
+        let tab = &mut self.tabs[self.active_tab];
+        std::mem::swap(&mut self.document, &mut tab.document);
+        std::mem::swap(&mut self.filename, &mut tab.filename);
+        std::mem::swap(&mut self.large_file, &mut tab.large_file);
+        std::mem::swap(&mut self.hex_editor, &mut tab.hex_editor);
+        std::mem::swap(&mut self.disk_stamp, &mut tab.disk_stamp);
+        std::mem::swap(&mut self.invalid_bytes, &mut tab.invalid_bytes);
+        std::mem::swap(&mut self.read_only, &mut tab.read_only);
+        std::mem::swap(&mut self.write_error, &mut tab.write_error);
+        std::mem::swap(&mut self.autosave, &mut tab.autosave);
+        std::mem::swap(&mut self.load_job, &mut tab.load_job);
+        std::mem::swap(&mut self.view, &mut tab.view);
+    }
+
+    pub fn switch_to_tab(&mut self, index: usize) {
+        if index == self.active_tab || index >= self.tabs.len() {
+            return;
+        }
+        self.swap_active();
+        self.active_tab = index;
+        self.swap_active();
+        // It may have changed on disk while it was in the background
+        self.check_disk(false);
+    }
+
+    // Opens an empty document in a new tab after the active one
+    pub fn new_tab(&mut self) {
+        let tab = Tab {
+            id: self.next_tab_id,
+            document: self.new_document(ropey::Rope::new()),
+            ..Tab::default()
+        };
+        self.next_tab_id += 1;
+        self.tabs.insert(self.active_tab + 1, tab);
+        self.switch_to_tab(self.active_tab + 1);
+    }
+
+    // A fresh Untitled tab that hasn't been touched can take the next file
+    // instead of leaving an empty tab behind
+    pub fn is_blank_tab(&self) -> bool {
+        self.filename.is_none()
+            && self.load_job.is_none()
+            && self.large_file.is_none()
+            && self.hex_editor.is_none()
+            && !self.document.is_modified()
+            && self.document.len_chars() == 0
+    }
+
+    // Brings the file to the front: the tab it is already open in, or a new one
+    // The tab the file is open in, however its path is spelled. A file that
+    // can't be found any more can only be matched by its name as it is.
+    pub fn tab_with_file(&self, file_path: &str) -> Option<usize> {
+        match fs::canonicalize(file_path) {
+            Ok(path) => self.tab_with_path(&path),
+            Err(_) => (0..self.tabs.len()).find(|&i| {
+                let filename = if i == self.active_tab { &self.filename } else { &self.tabs[i].filename };
+                filename.as_deref() == Some(file_path)
+            }),
+        }
+    }
+
+    pub fn open_in_tab(&mut self, file_path: String) {
+        match self.tab_with_file(&file_path) {
+            // Opening the file that is already in front loads it again
+            Some(i) if i == self.active_tab => {
+                if self.confirm_discard(Action::OpenFile(file_path.clone())) {
+                    self.start_loading_file(file_path);
+                }
+            }
+            Some(i) => self.switch_to_tab(i),
+            None => {
+                if !self.is_blank_tab() {
+                    self.new_tab();
+                }
+                self.start_loading_file(file_path);
+            }
+        }
+    }
+
+    pub fn switch_to_file(&mut self, file_path: &str) {
+        if let Some(index) = self.tab_with_file(file_path) {
+            self.switch_to_tab(index);
+        }
+    }
+
+    pub fn any_unsaved_changes(&self) -> bool {
+        self.has_unsaved_changes()
+            || self.tabs.iter().enumerate().any(|(i, tab)| i != self.active_tab && tab.has_unsaved_changes())
+    }
+
+    fn tab_has_unsaved_changes(&self, index: usize) -> bool {
+        if index == self.active_tab {
+            self.has_unsaved_changes()
+        } else {
+            self.tabs[index].has_unsaved_changes()
+        }
+    }
+
+    // Drops the active tab without asking. The last tab is replaced by an
+    // empty one rather than leaving nothing open.
+    fn remove_active_tab(&mut self) {
+        self.cancel_loading();
+        self.autosave.discard();
+        if self.tabs.len() == 1 {
+            self.new_file();
+            return;
+        }
+        let closing = self.active_tab;
+        let next = if closing + 1 < self.tabs.len() { closing + 1 } else { closing - 1 };
+        self.switch_to_tab(next);
+        self.tabs.remove(closing);
+        if self.active_tab > closing {
+            self.active_tab -= 1;
+        }
+    }
+
+    // Closes the active tab, asking first if it has unsaved changes
+    pub fn close_tab(&mut self) {
+        if self.confirm_discard(Action::RunCommand(Command::CloseTab)) {
+            self.remove_active_tab();
+        }
+    }
+
+    // Closes a tab from the tab strip and goes back to the tab that was in front
+    pub fn close_tab_at(&mut self, index: usize) {
+        let previous = self.tabs[self.active_tab].id;
+        self.switch_to_tab(index);
+        if self.confirm_discard(Action::RunCommand(Command::CloseTab)) {
+            self.remove_active_tab();
+            self.switch_to_tab_id(previous);
+        }
+    }
+
+    // Closes every tab but `keep`. Tabs with unsaved changes are brought to
+    // the front in turn to ask about them; cancelling stops there.
+    pub fn close_other_tabs(&mut self, keep: u64) {
+        while let Some(index) = self.tabs.iter().position(|tab| tab.id != keep) {
+            if self.tab_has_unsaved_changes(index) {
+                self.switch_to_tab(index);
+                if !self.confirm_discard(Action::CloseOtherTabs(keep)) {
+                    return;
+                }
+                self.remove_active_tab();
+            } else if index == self.active_tab {
+                self.remove_active_tab();
+            } else {
+                let tab = self.tabs.remove(index);
+                if let Some(job) = &tab.load_job {
+                    job.cancel();
+                }
+                let mut autosave = tab.autosave;
+                autosave.discard();
+                if self.active_tab > index {
+                    self.active_tab -= 1;
+                }
+            }
+        }
+        self.switch_to_tab_id(keep);
+    }
+
+    pub fn switch_to_tab_id(&mut self, id: u64) {
+        if let Some(index) = self.tabs.iter().position(|tab| tab.id == id) {
+            self.switch_to_tab(index);
+        }
+    }
+
+    // Asks about each tab with unsaved changes in turn. Returns true once
+    // nothing is left that the user hasn't saved or chosen to throw away.
+    pub fn confirm_close_all(&mut self) -> bool {
+        while let Some(index) = (0..self.tabs.len()).find(|&i| self.tab_has_unsaved_changes(i)) {
+            self.switch_to_tab(index);
+            if !self.confirm_discard(Action::RunCommand(Command::Quit)) {
+                return false;
+            }
+            self.remove_active_tab();
+        }
+        true
+    }
+
+    pub fn discard_all_autosaves(&mut self) {
+        self.autosave.discard();
+        for tab in &mut self.tabs {
+            tab.autosave.discard();
+        }
+    }
+
+    // Ctrl+Tab and Ctrl+Shift+Tab, wrapping around at either end
+    pub fn cycle_tabs(&mut self, forward: bool) {
+        let count = self.tabs.len();
+        let next = if forward { (self.active_tab + 1) % count } else { (self.active_tab + count - 1) % count };
+        self.switch_to_tab(next);
+    }
+
+    fn move_tab(&mut self, from: usize, to: usize) {
+        if from == to {
+            return;
+        }
+        let active_id = self.tabs[self.active_tab].id;
+        let tab = self.tabs.remove(from);
+        self.tabs.insert(to.min(self.tabs.len()), tab);
+        // The active tab's slot moves with it, the fields stay where they are
+        self.active_tab = self.tabs.iter().position(|tab| tab.id == active_id).unwrap_or(0);
+    }
+
+    pub fn editor_id(&self) -> egui::Id {
+        egui::Id::new(EDITOR_ID).with(self.tabs[self.active_tab].id)
+    }
+
+    // Title of the window, with a marker while the document has unsaved changes
+    pub fn window_title(&self) -> String {
+        let marker = if self.has_unsaved_changes() { "*" } else { "" };
+        let others = self.tabs.len() - 1;
+        let unsaved_others = (0..self.tabs.len())
+            .filter(|&i| i != self.active_tab && self.tabs[i].has_unsaved_changes())
+            .count();
+        let mut title = format!("{}{} - Amend", marker, tab_name(self.filename.as_deref()));
+        if others > 0 && unsaved_others > 0 {
+            title.push_str(&format!(" ({} other tabs unsaved)", unsaved_others));
+        }
+        title
+    }
+
+    pub fn show_tab_strip(&mut self, ui: &mut egui::Ui) {
+        let mut switch_to = None;
+        let mut close = None;
+        let mut close_others = None;
+        let mut moved = None;
+
+        ui.horizontal_wrapped(|ui| {
+            for i in 0..self.tabs.len() {
+                let (filename, unsaved, loading) = if i == self.active_tab {
+                    (self.filename.as_deref(), self.has_unsaved_changes(), self.is_loading())
+                } else {
+                    let tab = &self.tabs[i];
+                    (tab.filename.as_deref(), tab.has_unsaved_changes(), tab.load_job.as_ref().is_some_and(|j| j.is_active()))
+                };
+                let mut label = tab_name(filename);
+                if unsaved {
+                    label.insert(0, '*');
+                }
+                if loading {
+                    label.push('…');
+                }
+
+                let id = egui::Id::new("tab").with(self.tabs[i].id);
+                let response = ui.dnd_drag_source(id, i, |ui| {
+                    ui.horizontal(|ui| {
+                        let tab = ui.selectable_label(i == self.active_tab, label)
+                            .on_hover_text(filename.unwrap_or("Not saved yet"));
+                        if tab.clicked() {
+                            switch_to = Some(i);
+                        }
+                        // Middle click closes, as in most tabbed programs
+                        if tab.middle_clicked() {
+                            close = Some(i);
+                        }
+                        tab.context_menu(|ui| {
+                            if ui.button("Close").clicked() {
+                                close = Some(i);
+                            }
+                            if ui.button("Close Others").clicked() {
+                                close_others = Some(i);
+                            }
+                        });
+                        if ui.small_button("×").on_hover_text("Close tab").clicked() {
+                            close = Some(i);
+                        }
+                    });
+                }).response;
+
+                if let Some(from) = response.dnd_release_payload::<usize>() {
+                    moved = Some((*from, i));
+                }
+            }
+        });
+
+        if let Some((from, to)) = moved {
+            self.move_tab(from, to);
+        } else if let Some(i) = close {
+            self.close_tab_at(i);
+        } else if let Some(i) = close_others {
+            self.close_other_tabs(self.tabs[i].id);
+        } else if let Some(i) = switch_to {
+            self.switch_to_tab(i);
+        }
+    }
+}
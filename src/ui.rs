@@ -1,51 +1,122 @@
 use eframe::egui;
+use crate::actions::{Action, Command};
 use crate::app::TextEditorApp;
+use crate::dialogs::ReopenDialog;
+use crate::find::highlight_job;
+use crate::line_ending::LineEnding;
+
+pub const EDITOR_ID: &str = "editor_text";
 
 impl eframe::App for TextEditorApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // Start loading pending file after a few frames to ensure UI is ready
-        static mut FRAME_COUNT: u32 = 0;
-        unsafe {
-            FRAME_COUNT += 1;
-            if FRAME_COUNT >= 3 { // Wait 3 frames before starting file load
-                if let Some(file_path) = self.pending_file_to_load.take() {
-                    self.start_loading_file(file_path);
-                }
+        // Closing the window with unsaved changes asks first
+        if ctx.input(|i| i.viewport().close_requested()) && !self.closing {
+            if self.any_unsaved_changes() {
+                ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+                self.queue_action(Action::RunCommand(Command::Quit));
+            } else {
+                self.save_open_files(self.session_files(ctx));
+                self.closing = true;
+                self.discard_all_autosaves();
             }
         }
         
+        // Something else may have changed the file while another window had focus
+        let focused = ctx.input(|i| i.focused);
+        if focused && !self.window_focused {
+            self.check_disk(false);
+        }
+        self.window_focused = focused;
+        
         // Check if file loading is complete
-        if self.is_loading {
-            // Update text with partial content during loading
-            if let Ok(partial) = self.partial_content.lock() {
-                if !partial.is_empty() {
-                    self.text = partial.clone();
-                }
+        self.poll_load_job();
+        
+        // Files from the command line and the like, now that the UI is up
+        self.run_pending_actions(ctx);
+        self.autosave(ctx);
+        let is_loading = self.is_loading();
+        if is_loading {
+            // Keep the progress moving without waiting for input
+            ctx.request_repaint_after(std::time::Duration::from_millis(50));
+        }
+        
+        let title = self.window_title();
+        if title != self.window_title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.window_title = title;
+        }
+        
+        // Tab switching goes before the text edit, which would take Tab for itself
+        let (next_tab, previous_tab, close_tab) = ctx.input_mut(|i| {
+            let previous = i.consume_key(egui::Modifiers::CTRL | egui::Modifiers::SHIFT, egui::Key::Tab);
+            let next = i.consume_key(egui::Modifiers::CTRL, egui::Key::Tab);
+            let close = i.consume_key(egui::Modifiers::COMMAND, egui::Key::W);
+            (next, previous, close)
+        });
+        if next_tab {
+            self.cycle_tabs(true);
+        }
+        if previous_tab {
+            self.cycle_tabs(false);
+        }
+        if close_tab {
+            self.close_tab();
+        }
+        
+        // Undo and redo belong to the document, so take the shortcuts before the text edit sees them
+        if !is_loading && !self.read_only && self.large_file.is_none() && self.hex_editor.is_none() {
+            let (undo, redo) = ctx.input_mut(|i| {
+                let redo = i.consume_key(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::Z)
+                    || i.consume_key(egui::Modifiers::COMMAND, egui::Key::Y);
+                let undo = i.consume_key(egui::Modifiers::COMMAND, egui::Key::Z);
+                (undo, redo)
+            });
+            if undo {
+                self.undo(ctx);
             }
-            
-            if let Ok(mut pending) = self.pending_file_content.lock() {
-                if let Some((filename, content)) = pending.take() {
-                    self.text = content;
-                    self.filename = Some(filename);
-                    self.is_modified = false;
-                    self.is_loading = false;
-                    self.loading_filename = None;
-                    self.loading_progress = 0.0;
-                    
-                    // Clear partial content
-                    if let Ok(mut partial) = self.partial_content.lock() {
-                        *partial = String::new();
-                    }
-                }
+            if redo {
+                self.redo(ctx);
+            }
+        }
+        
+        // Find and Go To shortcuts work wherever the keyboard is
+        if !self.is_modal_open() {
+            let (find_in_files, find, replace, next, previous, goto) = ctx.input_mut(|i| {
+                // Before Ctrl+F, which would match it too
+                let find_in_files = i.consume_key(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::F);
+                let find = i.consume_key(egui::Modifiers::COMMAND, egui::Key::F);
+                let replace = i.consume_key(egui::Modifiers::COMMAND, egui::Key::H);
+                let previous = i.consume_key(egui::Modifiers::SHIFT, egui::Key::F3);
+                let next = i.consume_key(egui::Modifiers::NONE, egui::Key::F3);
+                let goto = i.consume_key(egui::Modifiers::COMMAND, egui::Key::G);
+                (find_in_files, find, replace, next, previous, goto)
+            });
+            if goto {
+                self.open_goto_dialog();
+            }
+            if find_in_files {
+                self.toggle_files_panel();
+            }
+            if (find || replace) && self.hex_editor.is_none() {
+                self.open_find_bar(ctx, replace);
+            }
+            if next || previous {
+                self.find_next(next);
             }
         }
+        self.update_search(ctx);
+        
+        // Side panels have to be laid out before the central panel
+        self.show_history_panel(ctx);
+        self.show_files_panel(ctx);
+        self.show_find_bar(ctx);
         
         egui::CentralPanel::default().show(ctx, |ui| {
             // Menu bar
             egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
                 ui.horizontal(|ui| {
                     if ui.button("New").clicked() {
-                        self.new_file();
+                        self.run_command(ctx, Command::New);
                     }
                     if ui.button("Open").clicked() {
                         self.open_file();
@@ -56,6 +127,70 @@ impl eframe::App for TextEditorApp {
                     if ui.button("Save As").clicked() {
                         self.save_file_as();
                     }
+                    let can_reopen = self.filename.is_some() && !is_loading;
+                    if ui.add_enabled(can_reopen, egui::Button::new("Reopen with Encoding…")).clicked() {
+                        self.reopen_dialog = Some(ReopenDialog {
+                            encoding: self.document.encoding().encoding,
+                        });
+                    }
+                    ui.menu_button("Open Recent", |ui| self.show_recent_menu(ui));
+                    let can_show_history = self.filename.is_some() && self.large_file.is_none();
+                    if ui.add_enabled(can_show_history, egui::Button::selectable(self.history_panel.is_some(), "History")).clicked() {
+                        self.toggle_history_panel();
+                    }
+                    if ui.add_enabled(!is_loading, egui::Button::new("Go To…")).on_hover_text("Ctrl+G").clicked() {
+                        self.open_goto_dialog();
+                    }
+                    if ui.add(egui::Button::selectable(self.files_panel.is_some(), "Find in Files"))
+                        .on_hover_text("Ctrl+Shift+F")
+                        .clicked()
+                    {
+                        self.toggle_files_panel();
+                    }
+                    ui.menu_button("Tabs", |ui| {
+                        for (label, command) in [
+                            ("Next Tab", Command::NextTab),
+                            ("Previous Tab", Command::PreviousTab),
+                            ("Close Tab", Command::CloseTab),
+                            ("Close Other Tabs", Command::CloseOtherTabs),
+                        ] {
+                            if ui.add_enabled(self.tabs.len() > 1 || command == Command::CloseTab, egui::Button::new(label)).clicked() {
+                                self.run_command(ctx, command);
+                                ui.close();
+                            }
+                        }
+                    });
+                    ui.menu_button("Options", |ui| {
+                        ui.checkbox(&mut self.save_in_place, "Save in place")
+                            .on_hover_text("Overwrite files directly so symlinks and hard links keep pointing at them. \
+                                Saves are no longer safe from crashes.");
+                        ui.separator();
+                        ui.label("Local history");
+                        ui.add(egui::DragValue::new(&mut self.history_retention.max_versions)
+                            .range(0..=1000)
+                            .suffix(" versions per file"));
+                        let mut days = self.history_retention.max_age.as_secs() / 86400;
+                        if ui.add(egui::DragValue::new(&mut days).range(1..=3650).prefix("for ").suffix(" days")).changed() {
+                            self.history_retention.max_age = std::time::Duration::from_secs(days * 86400);
+                        }
+                        ui.separator();
+                        if ui.checkbox(&mut self.session.reopen, "Reopen last session's files at startup").changed() {
+                            self.save_session();
+                        }
+                    });
+                    if ui.add_enabled(self.document.can_undo() && !self.read_only, egui::Button::new("Undo")).clicked() {
+                        self.undo(ctx);
+                    }
+                    if ui.add_enabled(self.document.can_redo() && !self.read_only, egui::Button::new("Redo")).clicked() {
+                        self.redo(ctx);
+                    }
+                    let read_only = egui::Button::selectable(self.read_only || self.large_file.is_some(), "Read-only");
+                    if ui.add_enabled(self.large_file.is_none(), read_only)
+                        .on_hover_text("View the document without being able to change it")
+                        .clicked()
+                    {
+                        self.toggle_read_only();
+                    }
                     
                     #[cfg(windows)]
                     if ui.button("Register as Context Menu Editor").clicked() {
@@ -64,14 +199,23 @@ impl eframe::App for TextEditorApp {
                     
                     ui.separator();
                     
-                    if self.is_loading {
-                        if let Some(filename) = &self.loading_filename {
-                            ui.label(format!("Loading: {}", filename));
+                    if let (Some(job), true) = (&self.load_job, is_loading) {
+                        ui.label(format!("Loading: {}", job.filename));
+                    } else if let Some(large_file) = &self.large_file {
+                        ui.label(format!("{} (read-only, large file)", large_file.path));
+                    } else if let (Some(hex_editor), Some(filename)) = (&self.hex_editor, &self.filename) {
+                        let kind = match self.document.compression() {
+                            Some(codec) => format!("binary, {}", codec.name()),
+                            None => "binary".to_string(),
+                        };
+                        let title = if hex_editor.modified {
+                            format!("*{} ({})", filename, kind)
                         } else {
-                            ui.label("Loading...");
-                        }
+                            format!("{} ({})", filename, kind)
+                        };
+                        ui.label(title);
                     } else if let Some(filename) = &self.filename {
-                        let title = if self.is_modified {
+                        let title = if self.document.is_modified() {
                             format!("*{}", filename)
                         } else {
                             filename.clone()
@@ -80,55 +224,165 @@ impl eframe::App for TextEditorApp {
                     } else {
                         ui.label("Untitled");
                     }
+                    
+                    if !is_loading && self.large_file.is_none() && self.hex_editor.is_none() {
+                        ui.separator();
+                        ui.label(self.document.encoding().name());
+                        if let Some(codec) = self.document.compression() {
+                            ui.label(codec.name()).on_hover_text("Decompressed while loading; saving compresses it again");
+                        }
+                        
+                        let line_ending = self.document.line_ending().name();
+                        let label = if self.document.has_mixed_line_endings() {
+                            egui::RichText::new(format!("Mixed ({} mostly)", line_ending)).color(ui.visuals().warn_fg_color)
+                        } else {
+                            egui::RichText::new(line_ending)
+                        };
+                        ui.menu_button(label, |ui| {
+                            for target in [LineEnding::Lf, LineEnding::CrLf] {
+                                let convert = egui::Button::new(format!("Convert to {}", target.name()));
+                                if ui.add_enabled(!self.read_only, convert).clicked() {
+                                    self.document.convert_line_endings(target);
+                                    ui.close();
+                                }
+                            }
+                        });
+                    }
+                    
+                    if let (Some(partial), false) = (self.document.partial(), is_loading) {
+                        let note = if self.document.compression().is_some() {
+                            format!("Partial: text of the first {} of {} compressed bytes loaded; it can only be saved as a new file",
+                                partial.loaded_bytes, partial.file_size)
+                        } else if partial.spliceable {
+                            format!("Partial: first {} of {} bytes loaded; saving keeps the rest of the file",
+                                partial.loaded_bytes, partial.file_size)
+                        } else {
+                            format!("Partial: first {} of {} bytes loaded; it can only be saved as a new file",
+                                partial.loaded_bytes, partial.file_size)
+                        };
+                        ui.colored_label(ui.visuals().warn_fg_color, note);
+                    }
+                    
+                    if self.invalid_bytes > 0 && !is_loading {
+                        ui.colored_label(
+                            ui.visuals().warn_fg_color,
+                            format!("{} bytes invalid in {} were replaced with U+FFFD; saving will not restore them",
+                                self.invalid_bytes, self.document.encoding().name()),
+                        );
+                    }
                 });
+                
+                self.show_read_only_bar(ui);
+                ui.separator();
+                self.show_tab_strip(ui);
             });
             
             // Text editor area with reduced height to make room for status
             ui.add_space(25.0);
             
             // Calculate available height for text editor (leave room for status bar)
-            let available_height = ui.available_height() - if self.is_loading { 50.0 } else { 0.0 };
+            let available_height = ui.available_height() - if self.load_job.is_some() { 50.0 } else { 0.0 };
             
-            // Always show the text editor, but disable during loading
-            egui::ScrollArea::both()
-                .max_height(available_height)
-                .show(ui, |ui| {
-                    let text_edit = egui::TextEdit::multiline(&mut self.text)
-                        .desired_width(f32::INFINITY)
-                        .desired_rows(25) // Reduced from 30 to make room for status
-                        .font(egui::TextStyle::Monospace)
-                        .interactive(!self.is_loading); // Disable during loading
-                    
-                    let response = ui.add(text_edit);
-                    
-                    // Track modifications (only when not loading)
-                    if response.changed() && !self.is_loading {
-                        self.is_modified = true;
+            if self.large_file.is_some() && !is_loading {
+                let mut edit_preview = None;
+                let results = self.find_bar.as_ref().and_then(|bar| bar.job()).map(|job| job.shared_results());
+                let results = results.as_ref().and_then(|results| results.lock().ok());
+                let marks = results.as_ref().map_or(&[][..], |results| &results.matches[..]);
+                if let Some(large_file) = &mut self.large_file {
+                    if large_file.show(ui, marks) {
+                        edit_preview = Some(large_file.path.clone());
                     }
-                });
+                }
+                if let Some(path) = edit_preview {
+                    self.start_loading_preview(path);
+                }
+            } else if let (Some(hex_editor), false) = (&mut self.hex_editor, is_loading) {
+                hex_editor.read_only = self.read_only;
+                hex_editor.show(ui);
+            } else {
+                // Always show the text editor, but disable during loading
+                let editor_id = self.editor_id();
+                let mut scroll_area = egui::ScrollArea::both()
+                    .id_salt(editor_id)
+                    .max_height(available_height);
+                if let Some(offset) = self.view.scroll_to.take() {
+                    scroll_area = scroll_area.scroll_offset(offset);
+                }
+                // A match from the find bar gets selected and scrolled to
+                let select = self.view.select.take();
+                if let Some((start, end)) = select {
+                    let mut state = egui::TextEdit::load_state(ctx, editor_id).unwrap_or_default();
+                    let range = egui::text::CCursorRange::two(egui::text::CCursor::new(start), egui::text::CCursor::new(end));
+                    state.cursor.set_char_range(Some(range));
+                    state.store(ctx, editor_id);
+                }
+                let results = self.find_bar.as_ref().and_then(|bar| bar.job()).map(|job| job.shared_results());
+                let mut highlighter = |ui: &egui::Ui, text: &dyn egui::TextBuffer, wrap_width: f32| {
+                    let results = results.as_ref().expect("only used while there are results");
+                    let job = highlight_job(ui, text.as_str(), results, wrap_width);
+                    ui.fonts(|fonts| fonts.layout_job(job))
+                };
+                let layouter: Option<&mut Layouter> = results.is_some().then_some(&mut highlighter);
+                let output = scroll_area.show(ui, |ui| {
+                        let output = if self.read_only {
+                            // An immutable buffer can still be selected and copied from
+                            let mut text = egui::TextBuffer::as_str(&self.document);
+                            editor_widget(&mut text, editor_id, is_loading, layouter).show(ui)
+                        } else {
+                            // Whatever the text edit does in one frame (e.g. paste over a selection) is one undo step
+                            self.document.begin_undo_group();
+                            let output = editor_widget(&mut self.document, editor_id, is_loading, layouter).show(ui);
+                            self.document.end_undo_group();
+                            
+                            // Clicking somewhere else ends the current typing run
+                            if output.response.clicked() {
+                                self.document.break_undo_group();
+                            }
+                            output
+                        };
+                        if let Some((_, end)) = select {
+                            let rect = output.galley.pos_from_cursor(egui::text::CCursor::new(end));
+                            ui.scroll_to_rect(rect.translate(output.galley_pos.to_vec2()), Some(egui::Align::Center));
+                        }
+                    });
+                self.view.offset = output.state.offset;
+            }
             
             // Show loading status at the bottom if loading
-            if self.is_loading {
+            let mut cancel = false;
+            let mut dismiss = false;
+            if let Some(job) = &self.load_job {
                 ui.add_space(10.0);
                 ui.horizontal(|ui| {
-                    if let Some(filename) = &self.loading_filename {
-                        ui.label(format!("Loading: {}", filename));
+                    if let Some(error) = job.error() {
+                        ui.colored_label(ui.visuals().error_fg_color, error);
+                        dismiss = ui.button("Dismiss").clicked();
+                        return;
                     }
+                    ui.label(format!("Loading: {}", job.filename));
                     
                     // Show bytes loaded
-                    if let Ok(bytes_loaded) = self.bytes_loaded.lock() {
-                        if let Ok(total_bytes) = self.total_bytes.lock() {
-                            if *total_bytes > 0 {
-                                let percentage = (*bytes_loaded as f32 / *total_bytes as f32 * 100.0) as u32;
-                                ui.label(format!("({} / {} bytes, {}%)", 
-                                    bytes_loaded, total_bytes, percentage));
-                            } else {
-                                ui.label(format!("({} bytes loaded)", bytes_loaded));
-                            }
+                    if let Some((bytes_loaded, total_bytes)) = job.progress() {
+                        if total_bytes > 0 {
+                            let percentage = (bytes_loaded as f32 / total_bytes as f32 * 100.0) as u32;
+                            ui.label(format!("({} / {} bytes, {}%)", 
+                                bytes_loaded, total_bytes, percentage));
+                        } else {
+                            ui.label(format!("({} bytes loaded)", bytes_loaded));
                         }
                     }
+                    cancel = ui.button("Cancel").clicked();
                 });
             }
+            if cancel {
+                if let Some(job) = &self.load_job {
+                    self.notifications.info(format!("Stopped loading {}", job.filename));
+                }
+                self.cancel_loading();
+            }
+            if dismiss {
+                self.load_job = None;
+            }
             
             // Keyboard shortcuts
             if ui.input(|i| i.key_pressed(egui::Key::S) && i.modifiers.ctrl) {
@@ -138,8 +392,60 @@ impl eframe::App for TextEditorApp {
                 self.open_file();
             }
             if ui.input(|i| i.key_pressed(egui::Key::N) && i.modifiers.ctrl) {
-                self.new_file();
+                self.run_command(ctx, Command::New);
             }
         });
+        
+        self.show_dialogs(ctx);
+        if let Some(action) = self.notifications.show(ctx) {
+            self.queue_action(action);
+        }
+    }
+}
+
+type Layouter<'t> = dyn FnMut(&egui::Ui, &dyn egui::TextBuffer, f32) -> std::sync::Arc<egui::Galley> + 't;
+
+fn editor_widget<'t>(
+    text: &'t mut dyn egui::TextBuffer,
+    id: egui::Id,
+    is_loading: bool,
+    layouter: Option<&'t mut Layouter<'_>>,
+) -> egui::TextEdit<'t> {
+    let text_edit = egui::TextEdit::multiline(text)
+        .id(id)
+        .desired_width(f32::INFINITY)
+        .desired_rows(25) // Reduced from 30 to make room for status
+        .font(egui::TextStyle::Monospace)
+        .interactive(!is_loading); // Disable during loading
+    match layouter {
+        Some(layouter) => text_edit.layouter(layouter),
+        None => text_edit,
+    }
+}
+
+impl TextEditorApp {
+    pub fn undo(&mut self, ctx: &egui::Context) {
+        if let Some(cursor) = self.document.undo() {
+            self.place_cursor(ctx, cursor);
+        }
+    }
+    
+    pub fn redo(&mut self, ctx: &egui::Context) {
+        if let Some(cursor) = self.document.redo() {
+            self.place_cursor(ctx, cursor);
+        }
+    }
+    
+    pub fn place_cursor(&self, ctx: &egui::Context, char_index: usize) {
+        self.place_cursor_in_tab(ctx, self.tabs[self.active_tab].id, char_index);
+    }
+
+    // Each tab's editor keeps its own state, so this works for tabs in the background too
+    pub fn place_cursor_in_tab(&self, ctx: &egui::Context, tab: u64, char_index: usize) {
+        let id = egui::Id::new(EDITOR_ID).with(tab);
+        let mut state = egui::TextEdit::load_state(ctx, id).unwrap_or_default();
+        let ccursor = egui::text::CCursor::new(char_index);
+        state.cursor.set_char_range(Some(egui::text::CCursorRange::one(ccursor)));
+        state.store(ctx, id);
     }
 }
@@ -0,0 +1,330 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_UNDO_DEPTH: usize = 1000;
+
+// Typing that pauses for longer than this starts a new undo step
+const GROUP_TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[derive(Clone, Debug)]
+pub enum Edit {
+    Insert { at: usize, text: String }, // Char index and inserted text
+    Delete { at: usize, text: String }, // Char index and removed text
+}
+
+impl Edit {
+    fn text(&self) -> &str {
+        match self {
+            Edit::Insert { text, .. } | Edit::Delete { text, .. } => text,
+        }
+    }
+
+    fn char_len(&self) -> usize {
+        self.text().chars().count()
+    }
+}
+
+// One undo step: everything in it is undone and redone together
+#[derive(Clone, Debug)]
+pub struct UndoGroup {
+    pub edits: Vec<Edit>,
+    sealed: bool, // Never merge further typing into this group
+    last_edit: Instant,
+}
+
+impl UndoGroup {
+    // Whether undoing or redoing this group can change which line endings the text uses
+    pub fn touches_line_endings(&self) -> bool {
+        self.edits.iter().any(|edit| edit.text().contains('\r'))
+    }
+}
+
+pub struct UndoHistory {
+    undo_stack: VecDeque<UndoGroup>,
+    redo_stack: Vec<UndoGroup>,
+    max_depth: usize,
+    saved_depth: Option<usize>, // Undo stack length at the last save, None if that state is gone
+    open_groups: usize, // Nesting depth of begin_group/end_group
+    pending: Vec<Edit>, // Edits collected while a group is open
+}
+
+impl Default for UndoHistory {
+    fn default() -> Self {
+        Self::with_depth(DEFAULT_UNDO_DEPTH)
+    }
+}
+
+impl UndoHistory {
+    pub fn with_depth(max_depth: usize) -> Self {
+        Self {
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            max_depth: max_depth.max(1),
+            saved_depth: Some(0),
+            open_groups: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth.max(1);
+        self.trim();
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    pub fn is_at_save_point(&self) -> bool {
+        self.pending.is_empty() && self.saved_depth == Some(self.undo_stack.len())
+    }
+
+    pub fn mark_saved(&mut self) {
+        if let Some(top) = self.undo_stack.back_mut() {
+            top.sealed = true;
+        }
+        self.saved_depth = Some(self.undo_stack.len());
+    }
+
+    pub fn begin_group(&mut self) {
+        self.open_groups += 1;
+    }
+
+    pub fn end_group(&mut self) {
+        if self.open_groups == 0 {
+            return;
+        }
+        self.open_groups -= 1;
+        if self.open_groups == 0 && !self.pending.is_empty() {
+            let edits = std::mem::take(&mut self.pending);
+            self.commit(edits);
+        }
+    }
+
+    // Stop the current typing run so the next edit becomes its own undo step
+    pub fn break_group(&mut self) {
+        if let Some(top) = self.undo_stack.back_mut() {
+            top.sealed = true;
+        }
+    }
+
+    pub fn record(&mut self, edit: Edit) {
+        if self.open_groups > 0 {
+            self.pending.push(edit);
+        } else {
+            self.commit(vec![edit]);
+        }
+    }
+
+    pub fn pop_undo(&mut self) -> Option<UndoGroup> {
+        let group = self.undo_stack.pop_back()?;
+        self.redo_stack.push(group.clone());
+        Some(group)
+    }
+
+    pub fn pop_redo(&mut self) -> Option<UndoGroup> {
+        let mut group = self.redo_stack.pop()?;
+        group.sealed = true;
+        self.undo_stack.push_back(group.clone());
+        Some(group)
+    }
+
+    fn commit(&mut self, edits: Vec<Edit>) {
+        let now = Instant::now();
+        self.redo_stack.clear();
+
+        // Once we branch off below the save point it can never be reached again
+        if let Some(saved) = self.saved_depth {
+            if saved > self.undo_stack.len() {
+                self.saved_depth = None;
+            }
+        }
+
+        if edits.len() == 1 && self.saved_depth != Some(self.undo_stack.len()) {
+            if let Some(top) = self.undo_stack.back_mut() {
+                if Self::can_merge(top, &edits[0], now) {
+                    Self::merge(top, edits.into_iter().next().unwrap());
+                    top.last_edit = now;
+                    return;
+                }
+            }
+        }
+
+        self.undo_stack.push_back(UndoGroup {
+            sealed: edits.len() > 1,
+            edits,
+            last_edit: now,
+        });
+        self.trim();
+    }
+
+    fn can_merge(top: &UndoGroup, edit: &Edit, now: Instant) -> bool {
+        if top.sealed || now.duration_since(top.last_edit) > GROUP_TIMEOUT {
+            return false;
+        }
+        let Some(last) = top.edits.last() else {
+            return false;
+        };
+        match (last, edit) {
+            (Edit::Insert { at: prev_at, text: prev }, Edit::Insert { at, text }) => {
+                // Keep typing in one step, but start a new one at each line break
+                *at == prev_at + last.char_len()
+                    && !text.contains('\n')
+                    && !prev.ends_with('\n')
+            }
+            (Edit::Delete { at: prev_at, .. }, Edit::Delete { at, .. }) => {
+                // Backspace runs end where the previous deletion started, Delete runs stay put
+                *at + edit.char_len() == *prev_at || at == prev_at
+            }
+            _ => false,
+        }
+    }
+
+    fn merge(top: &mut UndoGroup, edit: Edit) {
+        let Some(last) = top.edits.last_mut() else {
+            top.edits.push(edit);
+            return;
+        };
+        match (last, edit) {
+            (Edit::Insert { text: prev, .. }, Edit::Insert { text, .. }) => prev.push_str(&text),
+            (Edit::Delete { at: prev_at, text: prev }, Edit::Delete { at, text }) => {
+                if at < *prev_at {
+                    prev.insert_str(0, &text);
+                    *prev_at = at;
+                } else {
+                    prev.push_str(&text);
+                }
+            }
+            (_, edit) => top.edits.push(edit),
+        }
+    }
+
+    fn trim(&mut self) {
+        while self.undo_stack.len() > self.max_depth {
+            self.undo_stack.pop_front();
+            self.saved_depth = match self.saved_depth {
+                Some(depth) if depth > 0 => Some(depth - 1),
+                _ => None,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(at: usize, text: &str) -> Edit {
+        Edit::Insert { at, text: text.to_string() }
+    }
+
+    fn delete(at: usize, text: &str) -> Edit {
+        Edit::Delete { at, text: text.to_string() }
+    }
+
+    fn undo_texts(history: &mut UndoHistory) -> Vec<String> {
+        let mut texts = Vec::new();
+        while let Some(group) = history.pop_undo() {
+            texts.push(group.edits.iter().map(Edit::text).collect());
+        }
+        texts
+    }
+
+    #[test]
+    fn typing_is_grouped_until_a_line_break() {
+        let mut history = UndoHistory::default();
+        history.record(insert(0, "a"));
+        history.record(insert(1, "b"));
+        history.record(insert(2, "\n"));
+        history.record(insert(3, "c"));
+        // Typing somewhere else starts a new step too
+        history.record(insert(0, "d"));
+        assert_eq!(undo_texts(&mut history), ["d", "c", "\n", "ab"]);
+
+        // Backspace and Delete runs
+        let mut history = UndoHistory::default();
+        history.record(delete(2, "c"));
+        history.record(delete(1, "b"));
+        history.record(delete(1, "x"));
+        let group = history.pop_undo().unwrap();
+        assert!(matches!(&group.edits[..], [Edit::Delete { at: 1, text }] if text == "bcx"));
+    }
+
+    #[test]
+    fn break_group_and_explicit_groups() {
+        let mut history = UndoHistory::default();
+        history.record(insert(0, "a"));
+        history.break_group();
+        history.record(insert(1, "b"));
+        history.begin_group();
+        history.record(insert(2, "c"));
+        history.begin_group();
+        history.record(delete(0, "a"));
+        history.end_group();
+        history.end_group();
+        // Typing after a group doesn't join it
+        history.record(insert(3, "d"));
+        assert_eq!(undo_texts(&mut history), ["d", "ca", "b", "a"]);
+        assert!(history.can_redo());
+        history.record(insert(0, "e"));
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn max_depth_drops_the_oldest_steps() {
+        let mut history = UndoHistory::with_depth(3);
+        for (i, text) in ["a", "b", "c", "d"].into_iter().enumerate() {
+            history.record(insert(i, text));
+            history.break_group();
+        }
+        history.set_max_depth(2);
+        assert_eq!(history.max_depth(), 2);
+        assert_eq!(undo_texts(&mut history), ["d", "c"]);
+        assert_eq!(UndoHistory::with_depth(0).max_depth(), 1);
+    }
+
+    #[test]
+    fn save_point() {
+        let mut history = UndoHistory::default();
+        assert!(history.is_at_save_point());
+        history.record(insert(0, "a"));
+        history.mark_saved();
+        assert!(history.is_at_save_point());
+        // Typing on after saving is a step of its own, so undo gets back to the save
+        history.record(insert(1, "b"));
+        assert!(!history.is_at_save_point());
+        history.pop_undo();
+        assert!(history.is_at_save_point());
+        history.pop_redo();
+        assert!(!history.is_at_save_point());
+
+        // Undoing past the save and editing makes it unreachable
+        history.pop_undo();
+        history.pop_undo();
+        history.record(insert(0, "c"));
+        history.pop_undo();
+        assert!(!history.is_at_save_point());
+
+        // Dropping steps for depth keeps the save point, unless it was before them
+        let mut history = UndoHistory::with_depth(1);
+        history.record(insert(0, "a"));
+        history.mark_saved();
+        history.record(insert(1, "b"));
+        history.pop_undo();
+        assert!(history.is_at_save_point());
+        let mut history = UndoHistory::with_depth(1);
+        history.record(insert(0, "a"));
+        history.break_group();
+        history.record(insert(1, "b"));
+        history.pop_undo();
+        assert!(!history.is_at_save_point());
+    }
+}